@@ -0,0 +1,75 @@
+//! Connection setup latency with and without a session pool.
+//!
+//! This measures pre-warming in general, not the Kubernetes API: the
+//! sessions are plain TCP connections to a local echo server that only
+//! acknowledges them after a fixed delay. The delay stands for a slow
+//! handshake; no websocket upgrade or port-forward protocol is involved,
+//! so the numbers show what hiding a handshake of that length saves.
+//! Run with `cargo bench --bench session_pool`.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use portforward::{PFError, SessionPool};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Delay before the echo server acknowledges a connection.
+const HANDSHAKE: Duration = Duration::from_millis(20);
+const CONNECTIONS: u32 = 100;
+/// Time a client spends on each connection before opening the next one.
+const REQUEST: Duration = Duration::from_millis(5);
+
+async fn delayed_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                tokio::time::sleep(HANDSHAKE).await;
+                conn.write_all(b"ok").await?;
+                let mut buf = [0u8; 1];
+                while conn.read(&mut buf).await? > 0 {
+                    conn.write_all(&buf).await?;
+                }
+                Ok::<_, std::io::Error>(())
+            });
+        }
+    });
+    addr
+}
+
+async fn connect(addr: SocketAddr) -> Result<TcpStream, PFError> {
+    let mut conn = TcpStream::connect(addr).await?;
+    let mut ack = [0u8; 2];
+    conn.read_exact(&mut ack).await?;
+    Ok(conn)
+}
+
+async fn run(addr: SocketAddr, size: usize) -> Duration {
+    let pool = SessionPool::new(size, move || connect(addr));
+    // let the pool fill up before measuring
+    tokio::time::sleep(HANDSHAKE * 2).await;
+
+    let mut setup = Duration::ZERO;
+    for _ in 0..CONNECTIONS {
+        let started = Instant::now();
+        let mut conn = pool.get().await.unwrap();
+        setup += started.elapsed();
+
+        conn.write_all(b"x").await.unwrap();
+        let mut buf = [0u8; 1];
+        conn.read_exact(&mut buf).await.unwrap();
+        tokio::time::sleep(REQUEST).await;
+    }
+    setup / CONNECTIONS
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = delayed_echo_server().await;
+    for size in [0, 1, 4, 8] {
+        let mean = run(addr, size).await;
+        println!("pool size {:>2}: mean connection setup {:?} over {} connections", size, mean, CONNECTIONS);
+    }
+}
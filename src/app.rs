@@ -1,12 +1,16 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
-use iced::widget::{button, checkbox, column, container, row, text, Space};
+use iced::widget::{button, checkbox, column, container, pick_list, row, text, Space};
 use iced::{window, Command, Length, Settings, Size};
 use iced::multi_window::{self,Application};
-use serde::de;
-use crate::util::{file_dialog, load_deployment, port_forward};
-use crate::{theme, widget_namespace, widget_search_bar, Container, Element, Entry, EntryList, ForwardBox, Message, PFDeployment, Row};
-use crate::config::{Config, Deployment};
+use crate::forward::{ForwardKey, ForwardSpec, Forwards, RemotePort, Target};
+use crate::util::{check_permissions, load_deployment, load_namespace, open_dialog, port_forward, save_dialog};
+use crate::{error_view, RecentFile, theme, widget_namespace, widget_profile, Bind, ContainerPort, ForwardState, widget_search_bar, Container, Element, Entry, EntryList, ForwardBox, Message, NamespacePicker, PFDeployment, Permission, Result};
+use crate::config::{Config, Deployment, DeploymentConfig, Mapping, NamespaceHistory, Profile, RecentFiles, Session, Startup};
+use crate::k8s::{kube_contexts, Kind, WorkloadEvent};
+use tokio_util::sync::CancellationToken;
+use tracing::error;
 
 const WINDOW_SIZE: Size = Size::new(780.0, 720.0);
 fn application_icon() -> iced::window::Icon {
@@ -18,14 +22,23 @@ fn application_icon() -> iced::window::Icon {
 pub struct App {
     windows: HashMap<window::Id, Window>,
     next_window_pos: window::Position,
+    /// Where the main window is autosaved, `None` when it isn't.
+    session: Option<PathBuf>,
+    /// The config of the main window as last autosaved.
+    saved: Option<DeploymentConfig>,
+    /// An autosave is in flight, the next waits for it.
+    saving: bool,
+    recent: RecentFiles,
+    /// Where the recent files are kept, `None` without a config directory.
+    recent_path: Option<PathBuf>,
 }
 
 impl App {
     pub fn launch() -> iced::Result {
-        Self::run(Self::settings())
+        Self::run(Self::settings(Startup::from_args(std::env::args().skip(1))))
     }
 
-    pub fn settings() -> Settings<Config> {
+    pub fn settings(startup: Startup) -> Settings<Startup> {
         iced::Settings {
            window: window::Settings {
             icon: Some(application_icon()),
@@ -33,24 +46,74 @@ impl App {
             min_size: Some(WINDOW_SIZE),
             ..Default::default()
            },
+           flags: startup,
            ..Default::default()
         }
     }
 
+    /// Saves the config of the main window when it changed since the last
+    /// autosave. Saves run one at a time, changes made meanwhile are saved
+    /// once the running one is done.
+    fn autosave(&mut self) -> Command<Message> {
+        let Some(path) = self.session.clone().filter(|_| !self.saving) else {
+            return Command::none();
+        };
+        let Some(window) = self.windows.get(&window::Id::MAIN) else {
+            return Command::none();
+        };
+        if self.saved.as_ref() == Some(&window.config.deployment_config) {
+            return Command::none();
+        }
+        let config = window.config.deployment_config.clone();
+        self.saved = Some(config.clone());
+        self.saving = true;
+        Command::perform(async move { config.save(path).await }, Message::Autosaved)
+    }
+
+    /// Puts a config file saved or loaded on top of the recent files.
+    fn used_file(&mut self,file: PathBuf) -> Command<Message> {
+        self.recent.used(file);
+        let Some(path) = self.recent_path.clone() else {
+            return Command::none();
+        };
+        Command::perform(self.recent.clone().save(path), |_| Message::Ignore)
+    }
 }
 
 impl multi_window::Application for App {
     type Executor = iced::executor::Default;
     type Message = Message;
     type Theme = theme::Theme;
-    type Flags = Config;
+    type Flags = Startup;
 
-    fn new(_flags: Self::Flags) -> (Self, iced::Command<Self::Message>) {
+    fn new(startup: Self::Flags) -> (Self, iced::Command<Self::Message>) {
+        let mut window = Window::new();
+        let mut session = startup.session.clone();
+        let restore = match startup.session.as_ref().filter(|path| path.exists()) {
+            Some(path) => match window.restore(window::Id::MAIN,path.clone(),startup.restart) {
+                Ok(command) => command,
+                Err(e) => {
+                    // leave the file for the user rather than autosaving over it
+                    error!("not autosaving, {} could not be restored: {}",path.display(),e);
+                    window.config.data_config.config_error = Some(e);
+                    session = None;
+                    Command::none()
+                }
+            },
+            None => Command::none(),
+        };
+        let command = load_namespace(window::Id::MAIN,window.config.data_config.current_context.clone());
+        let recent_path = RecentFiles::path();
         let app = Self{
-            windows: HashMap::from([(window::Id::MAIN,Window::new())]),
-            next_window_pos: window::Position::Default
+            recent: recent_path.as_deref().map(RecentFiles::load).unwrap_or_default(),
+            recent_path,
+            saved: Some(window.config.deployment_config.clone()),
+            windows: HashMap::from([(window::Id::MAIN,window)]),
+            next_window_pos: window::Position::Default,
+            session,
+            saving: false,
         };
-        (app, Command::none())
+        (app, Command::batch([restore,command]))
     }
 
     fn title(&self, _id: iced::window::Id) -> String {
@@ -58,50 +121,181 @@ impl multi_window::Application for App {
     }
 
     fn update(&mut self, message: Self::Message) -> iced::Command<Self::Message> {
+        let command = self.dispatch(message);
+        Command::batch([command,self.autosave()])
+    }
+
+    fn view(
+        &self,
+        id: window::Id,
+    ) -> Element<'_, Message> {
+        let content = self.windows.get(&id).unwrap().view(id,&self.recent);
+
+        container(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+        
+    }
+
+    fn subscription(&self) -> iced::Subscription<Message> {
+        iced::event::listen_with(|event, _| match event {
+            iced::Event::Window(id, window::Event::Closed) => Some(Message::WindowClosed(id)),
+            _ => None,
+        })
+    }
+
+}
+
+impl App {
+    fn dispatch(&mut self, message: Message) -> Command<Message> {
         match message {
             Message::ListDeployment(id,v) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
-                window.fill(v)
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.fill(id,v);
+            }
+            Message::WorkloadChanged(id,event) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.changed(id,event);
+            }
+            Message::Permissions{id,namespace,missing} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.permissions(namespace,missing);
             }
             Message::FilterDeployment(id,v) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
                 window.config.data_config.search_value = v.clone();
                 window.filter();
             }
             Message::Load(id) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
-                let namespace = window.config.data_config.current_namespace.clone();
-                window.clear();
-                window.config.data_config.current_namespace = namespace.clone();
-                return load_deployment(id,namespace); 
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.load(id);
+            }
+            Message::Choose{id,namespace,name} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.select(namespace,name);      
             }
-            Message::Choose(id,name) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
-                window.select(name.clone());      
+            Message::Forward{id,name} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.forward(id,name);
             }
-            Message::Forward{id,name,port} => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
-                return window.forward(id,name, port);
+            Message::StopForward{id,name} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.stop(name);
+            }
+            Message::RestartForward{id,name} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.restart(id,name);
+            }
+            Message::ForwardStatus{id,key,handle,state} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.status(key,handle,state);
             }
             Message::InputForward{id,port} => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
                 window.config.data_config.current_port = port;
             }
-            Message::Error(id,v,t) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
-                if t == 0 {
-                    window.config.data_config.list_deployment_error = v;
-                } else if t == 1{
-                    window.forward_box = ForwardBox::Error(v);
+            Message::InputRemote{id,remote} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                // a typed port is looked up in every container
+                window.config.data_config.current_container = None;
+                window.config.data_config.current_remote = remote;
+            }
+            Message::InputBind{id,bind} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_bind = bind;
+            }
+            Message::InputPin{id,pin} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_pin = pin;
+            }
+            Message::ChoosePort{id,port} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                // service ports are not tied to a container
+                window.config.data_config.current_container = Some(port.container.clone()).filter(|container| !container.is_empty());
+                window.config.data_config.current_remote = port.remote().to_string();
+            }
+            Message::AddMapping(id) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.add_mapping(id);
+            }
+            Message::RemoveMapping{id,local} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.remove_mapping(local);
+            }
+            Message::InputWatch{id,watch} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_watch = watch;
+            }
+            Message::InputPool{id,pool} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_pool = pool;
+            }
+            Message::InputBalance{id,balance} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_balance = balance;
+            }
+            Message::Error(id,e) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.list_deployment_error = Some(e);
+            }
+            Message::ReloadNamespaces(id) => {
+                let Some(window) = self.windows.get(&id) else { return Command::none() };
+                return load_namespace(id,window.config.data_config.current_context.clone());
+            }
+            Message::SelectContext(id,context) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_context = Some(context.clone());
+                window.namespaces = NamespacePicker::default();
+                window.refresh_namespaces();
+                let namespaces = load_namespace(id,Some(context));
+                if window.config.data_config.all_namespaces || !window.config.data_config.current_namespace.is_empty() {
+                    return Command::batch([namespaces,self.update(Message::Load(id))]);
                 }
-                
+                // what was listed belongs to the previous context
+                window.listing.cancel();
+                window.clear();
+                return namespaces;
+            }
+            Message::ListNamespace(id,namespaces) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                match namespaces {
+                    Ok(namespaces) => {
+                        window.namespaces.listed = namespaces;
+                        window.namespaces.error = None;
+                    }
+                    Err(e) => window.namespaces.error = Some(e),
+                }
+                window.refresh_namespaces();
+            }
+            Message::PickNamespace(id,namespace) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_namespace = namespace;
+                return self.update(Message::Load(id));
+            }
+            Message::FavouriteNamespace(id) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                let namespace = window.config.data_config.current_namespace.clone();
+                window.namespace_history().toggle_favourite(&namespace);
+                window.refresh_namespaces();
             }
             Message::SelectNamespace(id,v) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
                 window.config.data_config.current_namespace = v.clone();
             }
+            Message::AllNamespaces(id,all) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.all_namespaces = all;
+                if all || !window.config.data_config.current_namespace.is_empty() {
+                    return self.update(Message::Load(id));
+                }
+                window.listing.cancel();
+                window.clear();
+            }
             Message::Forwarded(id,v) => {
-                let window = self.windows.get_mut(&id).expect("Window not found.");
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
                 window.config.data_config.check_forwarded = v;
                 window.filter();
             }
@@ -112,80 +306,98 @@ impl multi_window::Application for App {
                     ..Default::default()
                 });
 
-                self.windows.insert(id, Window::new());
+                let window = Window::new();
+                let context = window.config.data_config.current_context.clone();
+                self.windows.insert(id, window);
 
-                return spawn_window;
+                return Command::batch([spawn_window,load_namespace(id,context)]);
+            }
+            Message::WindowClosed(id) => {
+                // messages still on their way to the window are dropped
+                if let Some(mut window) = self.windows.remove(&id) {
+                    window.forwards.stop_all();
+                    window.listing.cancel();
+                }
             }
             Message::SaveConfigDialog(id) => {
-                return Command::perform(file_dialog(id), Message::SaveConfig)
+                return Command::perform(save_dialog(id), Message::SaveConfig)
             }
             Message::LoadConfigDialog(id) => {
-                return Command::perform(file_dialog(id), Message::LoadConfig)
+                return Command::perform(open_dialog(id), Message::LoadConfig)
             }
-            Message::SaveConfig(path) => {
-                if let Some((id,path)) = path {
-                    let window = self.windows.get_mut(&id).expect("Window not found.");
-                    let deployment_config = window.config.deployment_config.clone();
-                    return Command::perform(async move { deployment_config.save(path).await }, |_| Message::Ignore);
-                }
+            Message::SaveConfig(Some((id,path))) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                let deployment_config = window.config.deployment_config.clone();
+                return Command::perform(async move { deployment_config.save(path.clone()).await.map(|_| path) }, move |result| Message::SavedConfig(id,result));
             }
-            Message::LoadConfig(path) => {
-                if let Some((id,path)) = path {
-                    let window = self.windows.get_mut(&id).expect("Window not found.");
-                    if let Ok(_) = window.config.deployment_config.load(path) {
-                        let mut forward_command = Vec::<Command<Message>>::new();
-                        window.clear();
-                        
-                        for (namespace,deployments) in window.config.deployment_config.deployments.iter() {
-                            let mut count = 0;
-                            window.config.data_config.current_namespace = namespace.clone();
-
-                            for (name,deployment) in deployments.iter() {
-                                let succeed = deployment.forwarded  == 1 ;
-                               
-                                window.filter_deployments.entries.push(Entry { 
-                                    name: name.clone(), 
-                                    selected: false,
-                                    succeed
-                                });
-                                if succeed {
-                                    count += 1;
-                                    forward_command.push(port_forward(id,namespace.clone(),name.clone(), deployment.port));
-                                }
-                               
-                            }
-                            window.config.data_config.current_entries = deployments.len();
-                            window.config.data_config.current_succeed = count;
-                        }
-
-                        return Command::batch(forward_command);
-
+            Message::SavedConfig(id,result) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                match result {
+                    Ok(path) => {
+                        window.config.data_config.config_error = None;
+                        return self.used_file(path);
                     }
+                    Err(e) => window.config.data_config.config_error = Some(e),
+                }
+            }
+            Message::LoadConfig(Some((id,path))) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                if let Err(e) = window.config.deployment_config.load(path.clone()) {
+                    window.config.data_config.config_error = Some(e);
+                } else {
+                    window.config.data_config.config_error = None;
+                    let open = window.open(id);
+                    return Command::batch([open,self.used_file(path)]);
                 }
             }
 
+            Message::Autosaved(result) => {
+                self.saving = false;
+                if let Err(e) = result {
+                    error!("autosave: {}",e);
+                }
+            }
+            Message::RestartOnStartup(id,restart) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.deployment_config.session.restart = restart;
+            }
+            Message::SelectProfile(id,name) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.current_profile = Some(name);
+                window.config.data_config.profile_skipped.clear();
+            }
+            Message::InputProfile(id,name) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.profile_input = name;
+            }
+            Message::CreateProfile(id) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.create_profile();
+            }
+            Message::DeleteProfile(id) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.profile_skipped.clear();
+                window.delete_profile();
+            }
+            Message::StartProfile(id) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                return window.start_profile(id);
+            }
+            Message::StopProfile(id) => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.config.data_config.profile_skipped.clear();
+                window.stop_profile();
+            }
+            Message::ToggleProfileTarget{id,name} => {
+                let Some(window) = self.windows.get_mut(&id) else { return Command::none() };
+                window.toggle_profile_target(name);
+            }
             _ => {
                 
             }
         }
         Command::none()
     }
-
-    fn view(
-        &self,
-        id: window::Id,
-    ) -> Element<Message> {
-        let content = self.windows.get(&id).unwrap().view(id);
-
-        container(content)
-            .width(Length::Fill)
-            .height(Length::Fill)
-            .center_x()
-            .center_y()
-            .into()
-        
-    }
-
 }
 
 #[derive(Debug, Clone,Default)]
@@ -193,118 +405,609 @@ struct Window {
     filter_deployments: EntryList,
     forward_box: ForwardBox,
     config: Config,
+    forwards: Forwards,
+    /// Container ports of the listed deployments.
+    ports: HashMap<Target, Vec<ContainerPort>>,
+    /// Cancelled when the listed namespace is no longer followed.
+    listing: CancellationToken,
+    namespaces: NamespacePicker,
+    /// Permissions the current identity lacks, by listed namespace. A
+    /// namespace is in it as soon as its check is asked for.
+    missing: HashMap<String, Vec<Permission>>,
 }
 
  
 
 impl Window {
     fn new() -> Self {
-        Self::default()
+        let mut window = Self::default();
+        match kube_contexts() {
+            Ok((contexts,current)) => {
+                window.config.data_config.contexts = contexts;
+                window.config.data_config.current_context = current;
+            }
+            Err(e) => error!("{}",e),
+        }
+        window
+    }
+
+    /// Restores the session saved in `path`: its config, what it listed and,
+    /// when `restart` and the session asks for it, its forwards. Otherwise
+    /// nothing is started, but the forwards stay marked as forwarded so that
+    /// the autosave keeps them for a later restart.
+    fn restore(&mut self,id: window::Id,path: PathBuf,restart: bool) -> Result<Command<Message>> {
+        self.config.deployment_config.load(path)?;
+        let session = self.config.deployment_config.session.clone();
+        self.show_session();
+
+        let mut commands = vec![];
+        let forwarded: Vec<(Target,Deployment)> = match restart && session.restart {
+            true => self.config.deployment_config.clusters.iter().flat_map(|(context,namespaces)| {
+                namespaces.iter().flat_map(move |(namespace,deployments)| {
+                    deployments.iter()
+                        .filter(|(_,deployment)| deployment.forwarded)
+                        .map(move |(name,deployment)| ((context.clone(),namespace.clone(),name.clone()),deployment.clone()))
+                })
+            }).collect(),
+            false => vec![],
+        };
+        for (target,deployment) in forwarded {
+            for mapping in deployment.mappings.iter() {
+                commands.push(self.start(id,target.clone(),deployment.spec(&target.0,mapping)));
+            }
+        }
+        if self.config.data_config.all_namespaces || !self.config.data_config.current_namespace.is_empty() {
+            commands.push(self.load(id));
+        }
+        Ok(Command::batch(commands))
+    }
+
+    /// Shows the context and namespace a loaded config was saved with, when
+    /// the kubeconfig still has the context. Files saved before contexts
+    /// were recorded keep their targets under the empty context, the
+    /// current one.
+    fn show_session(&mut self) {
+        let session = self.config.deployment_config.session.clone();
+        match session.context {
+            Some(context) if self.config.data_config.contexts.contains(&context) => {
+                self.config.data_config.current_context = Some(context);
+            }
+            None if self.config.deployment_config.clusters.contains_key("") => {
+                self.config.data_config.current_context = None;
+            }
+            _ => {}
+        }
+        self.config.data_config.current_namespace = session.namespace;
+        self.config.data_config.all_namespaces = session.all_namespaces;
+    }
+
+    /// Shows a config loaded from a file in place of the window's: starts
+    /// the forwards it marks as forwarded, in every context, and lists its
+    /// targets in the context it was saved with. Targets of other contexts
+    /// are forwarded but not listed, the window acts on its own context.
+    fn open(&mut self,id: window::Id) -> Command<Message> {
+        self.forwards.stop_all();
+        // the targets shown come from the file from now on
+        self.listing.cancel();
+        self.clear();
+        let context = self.config.data_config.current_context.clone();
+        self.show_session();
+        // a file saved without a namespace shows every namespace it has
+        if self.config.data_config.current_namespace.is_empty() {
+            self.config.data_config.all_namespaces = true;
+        }
+
+        let mut commands = vec![];
+        let forwarded: Vec<(Target,Deployment)> = self.config.deployment_config.clusters.iter().flat_map(|(context,namespaces)| {
+            namespaces.iter().flat_map(move |(namespace,deployments)| {
+                deployments.iter()
+                    .filter(|(_,deployment)| deployment.forwarded)
+                    .map(move |(name,deployment)| ((context.clone(),namespace.clone(),name.clone()),deployment.clone()))
+            })
+        }).collect();
+        for (target,deployment) in forwarded {
+            for mapping in deployment.mappings.iter() {
+                commands.push(self.start(id,target.clone(),deployment.spec(&target.0,mapping)));
+            }
+        }
+
+        self.filter();
+        self.count_forwarded();
+        if self.config.data_config.current_context != context {
+            self.namespaces = NamespacePicker::default();
+            commands.push(load_namespace(id,self.config.data_config.current_context.clone()));
+        }
+        self.refresh_namespaces();
+        Command::batch(commands)
+    }
+
+    /// Lists the chosen namespace, or every namespace, and follows it in
+    /// place of what was listed before.
+    fn load(&mut self,id: window::Id) -> Command<Message> {
+        let namespace = self.config.data_config.current_namespace.clone();
+        self.clear();
+        self.config.data_config.current_namespace = namespace.clone();
+        let context = self.config.data_config.current_context.clone();
+        self.config.deployment_config.session = Session {
+            context: context.clone(),
+            namespace: namespace.clone(),
+            all_namespaces: self.config.data_config.all_namespaces,
+            ..self.config.deployment_config.session.clone()
+        };
+        let namespace = match self.config.data_config.all_namespaces {
+            true => None,
+            false => Some(namespace),
+        };
+        if let Some(namespace) = &namespace {
+            self.namespace_history().used(namespace);
+            self.refresh_namespaces();
+        }
+        // stop following the previous listing
+        self.listing.cancel();
+        self.listing = CancellationToken::new();
+        load_deployment(id,context,namespace,self.listing.clone())
     }
 
     pub fn clear(&mut self) {
         self.config.data_config.clear();
         self.filter_deployments.entries.clear();
         self.forward_box = ForwardBox::None;
+        self.missing.clear();
     }
 
-    pub fn fill(&mut self,deployments: Vec<PFDeployment>) {
-        let namespace = self.config.data_config.current_namespace.clone();
-        self.clear();
-        let deployment_map = self.config.deployment_config
-            .deployments
-            .entry(namespace.clone())
-            .or_insert(HashMap::<String,Deployment>::new());
-        
-        let mut succeed_count = 0;
+    fn namespace_history(&mut self) -> &mut NamespaceHistory {
+        let context = self.context();
+        self.config.deployment_config.namespaces.entry(context).or_default()
+    }
+
+    fn refresh_namespaces(&mut self) {
+        let history = self.namespace_history().clone();
+        self.namespaces.refresh(&history,&self.config.data_config.current_namespace);
+    }
+
+    fn context(&self) -> String {
+        self.config.data_config.current_context.clone().unwrap_or_default()
+    }
+
+    /// The target `name` refers to in the window's context and namespace.
+    fn target(&self,name: &str) -> Target {
+        (self.context(),self.config.data_config.current_namespace.clone(),name.to_string())
+    }
+
+    /// Whether the target is in the listed namespace, or in the window's
+    /// context in all-namespaces mode.
+    fn is_current(&self,(context,namespace,_): &Target) -> bool {
+        *context == self.context()
+            && (self.config.data_config.all_namespaces || *namespace == self.config.data_config.current_namespace)
+    }
+
+    /// The config of the listed namespaces, by namespace.
+    fn shown(&self) -> Vec<(&String,&HashMap<String,Deployment>)> {
+        let context = self.context();
+        let Some(namespaces) = self.config.deployment_config.clusters.get(&context) else {
+            return Vec::new();
+        };
+        namespaces.iter()
+            .filter(|(namespace,_)| self.config.data_config.all_namespaces || **namespace == self.config.data_config.current_namespace)
+            .collect()
+    }
 
+    /// Adds a page of the listing to the config and to the entries shown.
+    pub fn fill(&mut self,id: window::Id,deployments: Vec<PFDeployment>) -> Command<Message> {
+        let context = self.context();
+        let mut commands = vec![];
         for deployment in deployments {
-            let v_deployment = deployment_map.entry(deployment.name.clone()).or_insert(Deployment::default());
+            commands.push(self.check(id,&deployment.namespace));
+            let reference = deployment.reference();
+            let target = (context.clone(),deployment.namespace.clone(),reference.clone());
+            let forwarded = self.config.deployment_config.namespace_mut(&target.0,&target.1)
+                .entry(reference.clone())
+                .or_default()
+                .forwarded;
+            self.ports.insert(target.clone(), deployment.ports);
+            // the user may already be filtering while pages arrive
+            if !reference.contains(&self.config.data_config.search_value) || (!forwarded && self.config.data_config.check_forwarded) {
+                continue;
+            }
 
             self.filter_deployments.entries.push(Entry { 
-                name: deployment.name.clone(), 
+                namespace: target.1.clone(),
+                name: reference, 
                 selected: false,
-                succeed: v_deployment.forwarded == 1 
+                state: self.forwards.deployment_state(&target),
+                denied: self.denied(&target),
             });
+        }
+        self.count_forwarded();
+        Command::batch(commands)
+    }
 
-            if v_deployment.forwarded == 1 {
-                succeed_count += 1;
+    /// Applies a change of the listed namespaces to the config and to the
+    /// entries shown.
+    pub fn changed(&mut self,id: window::Id,event: WorkloadEvent) -> Command<Message> {
+        let context = self.context();
+        let mut command = Command::none();
+        match event {
+            WorkloadEvent::Listed(deployments) => return self.fill(id,deployments),
+            WorkloadEvent::Applied(deployment) => {
+                command = self.check(id,&deployment.namespace);
+                let reference = deployment.reference();
+                let target = (context,deployment.namespace.clone(),reference.clone());
+                self.ports.insert(target.clone(), deployment.ports);
+                let forwarded = self.config.deployment_config.namespace_mut(&target.0,&target.1)
+                    .entry(reference.clone())
+                    .or_default()
+                    .forwarded;
+                let shown = reference.contains(&self.config.data_config.search_value)
+                    && (forwarded || !self.config.data_config.check_forwarded);
+                let listed = self.filter_deployments.entries.iter().any(|entry| entry.namespace == target.1 && entry.name == reference);
+                if shown && !listed {
+                    self.filter_deployments.entries.push(Entry {
+                        namespace: target.1.clone(),
+                        name: reference,
+                        selected: false,
+                        state: self.forwards.deployment_state(&target),
+                        denied: self.denied(&target),
+                    });
+                }
+            }
+            WorkloadEvent::Deleted(namespace,reference) => {
+                self.filter_deployments.entries.retain(|entry| entry.namespace != namespace || entry.name != reference);
+                let target = (context,namespace,reference.clone());
+                self.ports.remove(&target);
+                // keep what the user configured for the target
+                let deployments = self.config.deployment_config.namespace_mut(&target.0,&target.1);
+                if deployments.get(&reference).is_some_and(|deployment| !deployment.forwarded && deployment.mappings.is_empty()) {
+                    deployments.remove(&reference);
+                }
+                if self.target(&self.config.data_config.current_deployment) == target {
+                    self.forward_box = ForwardBox::None;
+                }
             }
         }
-        self.config.data_config.current_namespace = namespace.clone();
-        self.config.data_config.current_entries = deployment_map.len();
-        self.config.data_config.current_succeed = succeed_count;
+        self.count_forwarded();
+        command
+    }
+
+    /// Asks what the current identity lacks in a namespace, unless already
+    /// asked since the listing started.
+    fn check(&mut self,id: window::Id,namespace: &str) -> Command<Message> {
+        if self.missing.contains_key(namespace) {
+            return Command::none();
+        }
+        self.missing.insert(namespace.to_string(), vec![]);
+        check_permissions(id,self.config.data_config.current_context.clone(),namespace.to_string())
+    }
+
+    /// The permissions forwarding to a target needs that the current
+    /// identity lacks.
+    fn missing(&self,(_,namespace,name): &Target) -> Vec<Permission> {
+        let needed = Permission::forward(Kind::parse(name).0);
+        self.missing.get(namespace).into_iter().flatten()
+            .filter(|permission| needed.contains(permission))
+            .copied()
+            .collect()
     }
 
-    pub fn select(&mut self,name: String) {
+    fn denied(&self,target: &Target) -> bool {
+        !self.missing(target).is_empty()
+    }
+
+    pub fn permissions(&mut self,namespace: String,missing: Vec<Permission>) {
+        // the listing may have moved on since the check was asked for
+        if !self.missing.contains_key(&namespace) {
+            return;
+        }
+        self.missing.insert(namespace, missing);
+        let context = self.context();
+        let entries = std::mem::take(&mut self.filter_deployments.entries);
+        self.filter_deployments.entries = entries.into_iter().map(|mut entry| {
+            entry.denied = self.denied(&(context.clone(),entry.namespace.clone(),entry.name.clone()));
+            entry
+        }).collect();
+    }
+
+    pub fn select(&mut self,namespace: String,name: String) {
         for entry in self.filter_deployments.entries.iter_mut() {
-            if entry.name == name {
-                entry.selected = true;
-            } else {
-                entry.selected = false;
-            }
+            entry.selected = entry.namespace == namespace && entry.name == name;
         }
-        let namespace = self.config.data_config.current_namespace.clone();
-        let deployments = self.config.deployment_config.deployments.get(namespace.as_str()).unwrap();
-        let deployment = deployments.get(name.as_str()).unwrap();
-        let port = deployment.port;
+        // in all-namespaces mode the entry may be in another namespace
+        self.config.data_config.current_namespace = namespace;
+        let target = self.target(&name);
+        let Some(deployment) = self.config.deployment_config.namespace(&target.0,&target.1).and_then(|deployments| deployments.get(name.as_str())) else {
+            return;
+        };
+        let ports = self.ports.get(&target).cloned().unwrap_or_default();
+        // suggest the first declared port for a deployment without mappings
+        let port = match deployment.mappings.is_empty() {
+            true => ports.first().map(|port| port.port.to_string()).unwrap_or_default(),
+            false => "".to_string(),
+        };
         self.config.data_config.current_deployment = name.clone();
-        self.config.data_config.current_port = port.to_string();
+        self.config.data_config.current_mappings = deployment.mappings.clone();
+        self.config.data_config.current_port = port;
+        self.config.data_config.current_bind = "".to_string();
+        self.config.data_config.current_ports = ports;
+        self.config.data_config.current_container = None;
+        self.config.data_config.current_remote = "".to_string();
+        self.config.data_config.current_watch = deployment.watch;
+        self.config.data_config.current_pool = deployment.pool.to_string();
+        self.config.data_config.current_balance = deployment.balance;
+        self.config.data_config.current_pin = deployment.pin.clone().unwrap_or_default();
         self.forward_box = ForwardBox::Selected;
     }
 
     pub fn filter(&mut self) {
-        if self.config.deployment_config.deployments.len() == 0 {
-            return;
-        }
-
         let search_value = self.config.data_config.search_value.clone();
         let forwarded = self.config.data_config.check_forwarded;
+        let context = self.context();
         
-        let deployments = self.config.deployment_config.deployments.get(self.config.data_config.current_namespace.clone().as_str()).unwrap();
-
-        let temp:Vec<Entry> = deployments.iter().filter(|entry| {
-            entry.0.contains(&search_value) && (if forwarded { entry.1.forwarded == 1} else { true })
-        }).map(|v|{
-            Entry { name: v.0.clone(), selected: false, succeed: v.1.forwarded == 1 }
+        let temp:Vec<Entry> = self.shown().into_iter().flat_map(|(namespace,deployments)| {
+            deployments.iter().map(move |entry| (namespace,entry))
+        }).filter(|(_,entry)| {
+            entry.0.contains(&search_value) && (if forwarded { entry.1.forwarded } else { true })
+        }).map(|(namespace,v)|{
+            let target = (context.clone(),namespace.clone(),v.0.clone());
+            Entry { namespace: namespace.clone(), name: v.0.clone(), selected: false, state: self.forwards.deployment_state(&target), denied: self.denied(&target) }
         }).collect();
-        self.filter_deployments.entries.clear();
-        for deployment in temp {
-            self.filter_deployments.entries.push(Entry { 
-                name: deployment.name.clone(), 
-                selected: deployment.selected,
-                succeed: deployment.succeed 
-            });  
+        self.filter_deployments.entries = temp;
+    }
+
+    /// The mapping typed into the inputs of the forward box.
+    fn input_mapping(&self) -> Option<Mapping> {
+        let data_config = &self.config.data_config;
+        let local = data_config.current_port.trim().parse::<u16>().ok().filter(|port| *port != 0)?;
+        Some(Mapping {
+            local,
+            bind: Bind::parse(data_config.current_bind.as_str())?,
+            container: data_config.current_container.clone(),
+            remote: RemotePort::parse(data_config.current_remote.as_str()),
+        })
+    }
+
+    fn clear_input(&mut self) {
+        self.config.data_config.current_port = "".to_string();
+        self.config.data_config.current_bind = "".to_string();
+        self.config.data_config.current_container = None;
+        self.config.data_config.current_remote = "".to_string();
+    }
+
+    /// Starts every mapping of the selected deployment, replacing the
+    /// forwards already running for it.
+    pub fn forward(&mut self,id:window::Id, name:String) -> Command<Message> {
+        if self.config.data_config.current_mappings.is_empty() {
+            if let Some(mapping) = self.input_mapping() {
+                self.config.data_config.current_mappings.push(mapping);
+                self.clear_input();
+            }
         }
+        let target = self.target(&name);
+        let data_config = &self.config.data_config;
+
+        let deployments = self.config.deployment_config.namespace_mut(&target.0,&target.1);
+        let deployment = deployments.entry(name.clone()).or_default();
+        deployment.mappings = data_config.current_mappings.clone();
+        deployment.watch = data_config.current_watch;
+        deployment.pool = data_config.current_pool.parse::<usize>().unwrap_or(0);
+        deployment.balance = data_config.current_balance;
+        deployment.pin = Some(data_config.current_pin.trim().to_string()).filter(|pin| !pin.is_empty());
+
+        self.start_deployment(id,&target)
     }
 
-    pub fn forward(&mut self,id:window::Id, name:String,port:u16) -> Command<Message> {
-        let namespace = self.config.data_config.current_namespace.clone();
+    /// (Re)starts the forwards of a target with the mappings configured for
+    /// it.
+    fn start_deployment(&mut self,id:window::Id,target:&Target) -> Command<Message> {
+        let Some(deployment) = self.config.deployment_config.deployment_mut(target) else {
+            return Command::none();
+        };
+        deployment.forwarded = !deployment.mappings.is_empty();
+        let deployment = deployment.clone();
+
+        self.forwards.stop_deployment(target);
+        let commands: Vec<_> = deployment.mappings.iter()
+            .map(|mapping| self.start(id,target.clone(),deployment.spec(&target.0,mapping)))
+            .collect();
+        if self.is_current(target) {
+            self.mark(target, self.forwards.deployment_state(target));
+            self.count_forwarded();
+        }
+
+        Command::batch(commands)
+    }
+
+    fn profile(&self) -> Option<&Profile> {
+        let name = self.config.data_config.current_profile.as_ref()?;
+        self.config.deployment_config.profiles.get(name)
+    }
+
+    /// Starts the targets of the selected profile. Targets without saved
+    /// mappings have nothing to forward, they are reported instead.
+    pub fn start_profile(&mut self,id:window::Id) -> Command<Message> {
+        let Some(profile) = self.profile().cloned() else {
+            return Command::none();
+        };
+        let (targets,skipped): (Vec<Target>,Vec<Target>) = profile.targets().partition(|(context,namespace,name)| {
+            self.config.deployment_config.namespace(context,namespace)
+                .and_then(|deployments| deployments.get(name))
+                .is_some_and(|deployment| !deployment.mappings.is_empty())
+        });
+        self.config.data_config.profile_skipped = skipped.into_iter()
+            .map(|(context,namespace,name)| match context.is_empty() {
+                true => format!("{}/{}",namespace,name),
+                false => format!("{}:{}/{}",context,namespace,name),
+            })
+            .collect();
+        let commands: Vec<_> = targets.iter().map(|target| self.start_deployment(id,target)).collect();
+        Command::batch(commands)
+    }
+
+    pub fn stop_profile(&mut self) {
+        let Some(profile) = self.profile().cloned() else {
+            return;
+        };
+        for target in profile.targets() {
+            self.stop_deployment(target);
+        }
+    }
+
+    pub fn create_profile(&mut self) {
+        let name = self.config.data_config.profile_input.trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        self.config.deployment_config.profiles.entry(name.clone()).or_default();
+        self.config.data_config.current_profile = Some(name);
+        self.config.data_config.profile_input.clear();
+    }
+
+    pub fn delete_profile(&mut self) {
+        if let Some(name) = self.config.data_config.current_profile.take() {
+            self.config.deployment_config.profiles.remove(&name);
+        }
+    }
+
+    pub fn toggle_profile_target(&mut self,name: String) {
+        let target = self.target(&name);
+        let Some(profile) = self.config.data_config.current_profile.as_ref() else {
+            return;
+        };
+        if let Some(profile) = self.config.deployment_config.profiles.get_mut(profile) {
+            profile.toggle(&target);
+        }
+    }
+
+    fn start(&mut self,id:window::Id,target:Target,spec:ForwardSpec) -> Command<Message> {
+        let key = (target,spec.port);
+        let handle = self.forwards.start(key.clone(), spec);
+        port_forward(id,key,&handle)
+    }
+
+    pub fn restart(&mut self,id:window::Id, name:String) -> Command<Message> {
+        let target = self.target(&name);
+        let commands: Vec<_> = self.forwards.running(&target).into_iter()
+            .map(|handle| self.start(id,target.clone(),handle.spec))
+            .collect();
+        self.mark(&target, self.forwards.deployment_state(&target));
+        Command::batch(commands)
+    }
+
+    pub fn stop(&mut self,name:String) {
+        let target = self.target(&name);
+        self.stop_deployment(target);
+    }
+
+    fn stop_deployment(&mut self,target:Target) {
+        self.forwards.stop_deployment(&target);
+        self.unforward(target);
+    }
+
+    /// Adds the typed mapping to the selected deployment, and starts it
+    /// right away when the deployment is forwarded.
+    pub fn add_mapping(&mut self,id:window::Id) -> Command<Message> {
+        let Some(mapping) = self.input_mapping() else {
+            return Command::none();
+        };
+        self.clear_input();
+        let target = self.target(&self.config.data_config.current_deployment);
+        let mappings = &mut self.config.data_config.current_mappings;
+        mappings.retain(|m| m.local != mapping.local);
+        mappings.push(mapping.clone());
+        let mappings = mappings.clone();
+
+        let Some(deployment) = self.config.deployment_config.deployment_mut(&target) else {
+            return Command::none();
+        };
+        deployment.mappings = mappings;
+        // a restored target may be marked forwarded without running
+        if !deployment.forwarded || self.forwards.running(&target).is_empty() {
+            return Command::none();
+        }
+        let spec = deployment.spec(&target.0,&mapping);
+        let command = self.start(id,target.clone(),spec);
+        self.mark(&target, self.forwards.deployment_state(&target));
+        command
+    }
+
+    pub fn remove_mapping(&mut self,local:u16) {
+        let target = self.target(&self.config.data_config.current_deployment);
+        self.config.data_config.current_mappings.retain(|m| m.local != local);
+        if let Some(deployment) = self.config.deployment_config.deployment_mut(&target) {
+            deployment.mappings.retain(|m| m.local != local);
+        }
+        self.forwards.forget(&(target.clone(),local));
+        if self.forwards.running(&target).is_empty() {
+            self.unforward(target);
+        } else {
+            self.mark(&target, self.forwards.deployment_state(&target));
+        }
+    }
+
+    /// Applies a state reported by a forward task. Once every forward of a
+    /// deployment returned, either because it was stopped or because it
+    /// failed, the deployment is no longer marked as forwarded.
+    pub fn status(&mut self,key:ForwardKey,handle:u64,state:ForwardState) {
+        if !self.forwards.update(&key, handle, state.clone()) {
+            return;
+        }
+        let (target,_) = key;
+        if state.is_terminal() && self.forwards.running(&target).is_empty() {
+            self.unforward(target);
+        } else if self.is_current(&target) {
+            self.mark(&target, self.forwards.deployment_state(&target));
+        }
+    }
+
+    fn unforward(&mut self,target:Target) {
+        if let Some(deployment) = self.config.deployment_config.deployment_mut(&target) {
+            deployment.forwarded = false;
+        }
+        if self.is_current(&target) {
+            self.mark(&target, self.forwards.deployment_state(&target));
+            self.count_forwarded();
+        }
+    }
+
+    fn mark(&mut self,(_,namespace,name):&Target,state:ForwardState) {
         for entry in self.filter_deployments.entries.iter_mut() {
-            if entry.name == name {
-                entry.succeed = true;
+            if entry.namespace == *namespace && entry.name == *name {
+                entry.state = state.clone();
             }
         }
+    }
 
-        let deployments = self.config.deployment_config.deployments.entry(namespace.clone()).or_insert(HashMap::<String,Deployment>::new());
-        let deployment = deployments.entry(name.clone()).or_insert(Deployment::default());
-        deployment.port = port;
-        deployment.forwarded = 1;
-        
-
-        self.config.data_config.current_succeed = deployments.iter().filter(|entry| {
-            entry.1.forwarded == 1
-        }).count();
-        return port_forward(id,namespace,name.clone(), port);
+    fn count_forwarded(&mut self) {
+        let shown = self.shown();
+        let entries = shown.iter().map(|(_,deployments)| deployments.len()).sum();
+        let context = self.context();
+        // forwards restored without a restart are marked but not running
+        let succeed = shown.iter().map(|(namespace,deployments)| {
+            deployments.keys().filter(|name| !self.forwards.running(&(context.clone(),namespace.to_string(),name.to_string())).is_empty()).count()
+        }).sum();
+        self.config.data_config.current_entries = entries;
+        self.config.data_config.current_succeed = succeed;
     }
 
-    fn view(&self,id: window::Id) -> Element<Message> {
+    fn view<'a>(&'a self,id: window::Id,recent: &RecentFiles) -> Element<'a, Message> {
         
-        let namespace_box = widget_namespace(id,&self.config.data_config);
-        let forward_box = self.forward_box.view(id,&self.config.data_config);
+        let namespace_box = widget_namespace(id,&self.config.data_config,&self.namespaces);
+        let target = self.target(&self.config.data_config.current_deployment);
+        let states = self.config.data_config.current_mappings.iter()
+            .map(|mapping| self.forwards.state(&(target.clone(),mapping.local)))
+            .collect();
+        let running = !self.forwards.running(&target).is_empty();
+        let profile = self.profile().map(|profile| profile.contains(&target));
+        let forward_box = self.forward_box.view(id,&self.config.data_config,states,running,self.missing(&target),profile);
+
+        let profile = self.profile().map(|profile| {
+            let forwarding = profile.targets().filter(|target| !self.forwards.running(target).is_empty()).count();
+            (profile.targets.len(),forwarding)
+        });
+        let profile_box = widget_profile(id,&self.config.data_config,self.config.deployment_config.profiles.keys().cloned().collect(),profile);
 
         let left_view = column![
             namespace_box,
+            profile_box,
             forward_box,
         ].spacing(10);
 
@@ -313,6 +1016,9 @@ impl Window {
         let info_bar = row![
             text(format!("DEPLOYMENTS: {}    FORWARDING: {}",self.config.data_config.current_entries,self.config.data_config.current_succeed)),
             Space::with_width(Length::Fill),
+            checkbox("ALL NAMESPACES",self.config.data_config.all_namespaces)
+                .on_toggle(move |v| {Message::AllNamespaces(id,v)})
+                .style(theme::CheckBox::Inverted),
             checkbox("FORWARDED",self.config.data_config.check_forwarded)
                 .on_toggle(move |v| {Message::Forwarded(id,v)})
                 .style(theme::CheckBox::Inverted)
@@ -320,7 +1026,7 @@ impl Window {
         .spacing(8)
         .align_items(iced::Alignment::Center);
 
-        let entry_list = self.filter_deployments.view(id,self.config.data_config.list_deployment_error.clone());
+        let entry_list = self.filter_deployments.view(id,self.config.data_config.list_deployment_error.as_ref(),self.config.data_config.all_namespaces);
        
         let right_view = column![
              search_bar,
@@ -342,7 +1048,15 @@ impl Window {
         let bottom_buttons = row![
             button("Save Config").on_press(Message::SaveConfigDialog(id)).style(theme::Button::Primary),
             button("Load Config").on_press(Message::LoadConfigDialog(id)).style(theme::Button::Primary),
+            pick_list(recent.files.iter().cloned().map(RecentFile).collect::<Vec<_>>(),None::<RecentFile>,move |file| Message::LoadConfig(Some((id,file.0))))
+                .placeholder("recent files"),
         ]
+        .push_maybe((id == window::Id::MAIN).then(|| {
+            checkbox("restart forwards on startup",self.config.deployment_config.session.restart)
+                .on_toggle(move |v| Message::RestartOnStartup(id,v))
+                .style(theme::CheckBox::Inverted)
+        }))
+        .push_maybe(self.config.data_config.config_error.as_ref().map(|error| error_view(error,None)))
         .spacing(8)
         .align_items(iced::Alignment::Center);
 
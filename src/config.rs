@@ -1,8 +1,10 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::{BTreeMap, HashMap}, fmt::Display, fs, net::IpAddr, path::{Path, PathBuf}, sync::Arc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use tokio::io::AsyncWriteExt;
-use tracing::error;
-use crate::{PFError, Result};
+use tracing::{error, warn};
+use crate::forward::Target;
+use crate::{Balance, Bind, ContainerPort, ForwardSpec, PFError, RemotePort, Result};
 
 #[derive(Debug,Default,Clone)]
 pub struct Config{
@@ -11,57 +13,452 @@ pub struct Config{
 }
 
 
-#[derive(Debug,Default,Clone,Serialize,Deserialize)]
+/// Version of the saved config. Bump it with a step in [`MIGRATIONS`]
+/// whenever the saved format changes.
+pub const VERSION: u64 = 1;
+
+/// Upgrades a saved config by one version, indexed by the version it
+/// upgrades from. Steps work on the raw JSON so that old fields need no
+/// place in the current types.
+const MIGRATIONS: [fn(&mut Map<String,Value>); VERSION as usize] = [
+    migrate_unversioned,
+];
+
+/// Files saved before the config had a version: targets keyed by namespace
+/// alone, or already by context, with a single port each and `forwarded`
+/// stored as 0 or 1.
+fn migrate_unversioned(config: &mut Map<String,Value>) {
+    let mut clusters = match config.remove("clusters") {
+        Some(Value::Object(clusters)) => clusters,
+        _ => Map::new(),
+    };
+    if let Some(Value::Object(namespaces)) = config.remove("deployments") {
+        for (namespace,deployments) in namespaces {
+            let Value::Object(deployments) = deployments else { continue };
+            for (name,deployment) in deployments {
+                let context = deployment.get("context").and_then(Value::as_str).unwrap_or_default().to_string();
+                let namespaces = clusters.entry(context).or_insert_with(|| Value::Object(Map::new()));
+                if let Some(deployments) = namespaces.as_object_mut()
+                    .and_then(|namespaces| namespaces.entry(namespace.clone()).or_insert_with(|| Value::Object(Map::new())).as_object_mut()) {
+                    deployments.insert(name, deployment);
+                }
+            }
+        }
+    }
+    let deployments = clusters.values_mut()
+        .filter_map(Value::as_object_mut).flat_map(|namespaces| namespaces.values_mut())
+        .filter_map(Value::as_object_mut).flat_map(|deployments| deployments.values_mut())
+        .filter_map(Value::as_object_mut);
+    for deployment in deployments {
+        deployment.remove("context");
+        let port = deployment.remove("port").and_then(|port| port.as_u64()).unwrap_or_default();
+        let container = deployment.remove("container").unwrap_or(Value::Null);
+        let remote = deployment.remove("remote").unwrap_or(Value::Null);
+        let mappings = deployment.entry("mappings").or_insert_with(|| Value::Array(vec![]));
+        if mappings.as_array().is_some_and(|mappings| mappings.is_empty()) && port != 0 {
+            *mappings = serde_json::json!([{ "local": port, "container": container, "remote": remote }]);
+        }
+        if let Some(forwarded) = deployment.get("forwarded").and_then(Value::as_u64) {
+            deployment.insert("forwarded".into(), Value::Bool(forwarded == 1));
+        }
+    }
+    config.insert("clusters".into(), Value::Object(clusters));
+}
+
+/// Formats the config is saved in, told apart by the file extension. Files
+/// with any other extension are JSON.
+#[derive(Debug,Default,Clone,Copy,PartialEq,Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Json, Format::Yaml, Format::Toml];
+
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Format::Yaml,
+            "toml" => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["json"],
+            Format::Yaml => &["yaml", "yml"],
+            Format::Toml => &["toml"],
+        }
+    }
+
+    fn parse(self,content: &str) -> Result<Value> {
+        match self {
+            Format::Json => serde_json::from_str(content).map_err(|e| PFError::ConfigParse(Arc::new(e))),
+            Format::Yaml => serde_yaml::from_str(content).map_err(|e| PFError::ConfigParse(Arc::new(e))),
+            Format::Toml => toml::from_str(content).map_err(|e| PFError::ConfigParse(Arc::new(e))),
+        }
+    }
+
+    fn write(self,config: &Value) -> Result<String> {
+        match self {
+            Format::Json => serde_json::to_string_pretty(config).map_err(|e| PFError::WriteConfig(Arc::new(e))),
+            Format::Yaml => serde_yaml::to_string(config).map_err(|e| PFError::WriteConfig(Arc::new(e))),
+            // TOML has no null, unset fields are left out instead
+            Format::Toml => toml::to_string_pretty(&without_nulls(config.clone())).map_err(|e| PFError::WriteConfig(Arc::new(e))),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Json => write!(f, "JSON"),
+            Format::Yaml => write!(f, "YAML"),
+            Format::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter()
+            .filter(|(_,value)| !value.is_null())
+            .map(|(key,value)| (key,without_nulls(value)))
+            .collect()),
+        Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+        value => value,
+    }
+}
+
+#[derive(Debug,Default,Clone,PartialEq,Serialize,Deserialize)]
 pub struct DeploymentConfig {
-    pub deployments: HashMap<String,HashMap<String,Deployment>>,
+    /// Targets by kubeconfig context, namespace, then reference: `kind/name`,
+    /// or the bare name of a deployment. The empty context stands for the
+    /// current one.
+    #[serde(default)]
+    pub clusters: HashMap<String,HashMap<String,HashMap<String,Deployment>>>,
+    /// Favourite and recently used namespaces by kubeconfig context.
+    #[serde(default)]
+    pub namespaces: HashMap<String,NamespaceHistory>,
+    #[serde(default)]
+    pub session: Session,
+    /// Named groups of targets, started and stopped together.
+    #[serde(default)]
+    pub profiles: BTreeMap<String,Profile>,
 }
 
-#[derive(Debug,Default,Clone,Serialize,Deserialize)]
-pub struct Deployment {
-    pub port: u16,
-    pub forwarded: u8 // 0 false 1 true
+/// Targets across namespaces and contexts, forwarded with the mappings
+/// configured for each.
+#[derive(Debug,Default,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub targets: Vec<ProfileTarget>,
 }
 
-impl DeploymentConfig {
+#[derive(Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct ProfileTarget {
+    /// Empty for the current context.
+    #[serde(default)]
+    pub context: String,
+    pub namespace: String,
+    pub reference: String,
+}
 
-    pub fn clear(&mut self) {
-        self.deployments.clear();
+impl Profile {
+    pub fn targets(&self) -> impl Iterator<Item = Target> + '_ {
+        self.targets.iter().map(|target| (target.context.clone(),target.namespace.clone(),target.reference.clone()))
     }
 
-    pub fn load(&mut self,path: PathBuf) -> Result<()> {
-        let Ok(cfg) = fs::read_to_string(path.as_path()) else {
-            return Err(Box::new(PFError::LoadConfigBad));
+    pub fn contains(&self,target: &Target) -> bool {
+        self.targets().any(|t| t == *target)
+    }
+
+    /// Adds the target, or removes it when already in the profile.
+    pub fn toggle(&mut self,(context,namespace,reference): &Target) {
+        let len = self.targets.len();
+        self.targets.retain(|target| (&target.context,&target.namespace,&target.reference) != (context,namespace,reference));
+        if self.targets.len() == len {
+            self.targets.push(ProfileTarget { context: context.clone(), namespace: namespace.clone(), reference: reference.clone() });
+        }
+    }
+}
+
+/// What the main window showed when the app last ran.
+#[derive(Debug,Default,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    pub context: Option<String>,
+    #[serde(default)]
+    pub namespace: String,
+    #[serde(default)]
+    pub all_namespaces: bool,
+    /// Start the forwards saved as forwarded when the app starts.
+    #[serde(default)]
+    pub restart: bool,
+}
+
+/// The file the main window is autosaved to and restored from, in the
+/// per-user config directory.
+pub fn session_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("portforward").join("session.json"))
+}
+
+/// Config files saved or loaded lately, most recent first. Kept next to
+/// the session rather than in it, saved configs are shared with others.
+#[derive(Debug,Default,Clone,PartialEq,Serialize,Deserialize)]
+pub struct RecentFiles {
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    const MAX: usize = 10;
+
+    /// Where the list is kept, next to the default session whether or not
+    /// the session is kept.
+    pub fn path() -> Option<PathBuf> {
+        session_path().map(|session| session.with_file_name("recent.json"))
+    }
+
+    /// Reads the list, empty when there is none yet or it can't be read.
+    pub fn load(path: &Path) -> Self {
+        let Ok(content) = fs::read_to_string(path) else {
+            return Self::default();
         };
-        let result = serde_json::from_str(&cfg).unwrap_or_else(|e|{
-            error!("{}",e);
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            error!("{}: {}",path.display(),e);
             Self::default()
-        });
+        })
+    }
 
-        self.deployments = result.deployments;
+    pub fn used(&mut self,file: PathBuf) {
+        self.files.retain(|recent| *recent != file);
+        self.files.insert(0, file);
+        self.files.truncate(Self::MAX);
+    }
 
-        Ok(())
+    pub async fn save(self,path: PathBuf) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self).map_err(|e| PFError::WriteConfig(Arc::new(e)))?;
+        write_atomic(&path, &content).await
     }
+}
 
-    pub async fn save(&self,path: PathBuf) -> Result<()>{
-        let file = tokio::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(path.as_path())
-            .await;
-        if let Err(e)  = &file{
-            error!("{}",e);
-            return Err(Box::new(PFError::WriteConfigBad));
+/// Replaces the file at `path` by writing a temporary file next to it and
+/// renaming it over, so a failed write never leaves a truncated file
+/// behind. The file replaced is kept as `<name>.bak`.
+async fn write_atomic(path: &Path,content: &str) -> Result<()> {
+    let write = |e: std::io::Error| {
+        error!("{}: {}",path.display(),e);
+        PFError::WriteConfig(Arc::new(e))
+    };
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(write)?;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    let written = async {
+        let mut file = tokio::fs::File::create(&temp).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await
+    }.await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(write(e));
+    }
+    if tokio::fs::try_exists(path).await.unwrap_or(false) {
+        tokio::fs::copy(path, path.with_file_name(format!("{}.bak", name))).await.map_err(write)?;
+    }
+    tokio::fs::rename(&temp, path).await.map_err(write)?;
+    Ok(())
+}
+
+/// How the app starts.
+#[derive(Debug,Clone)]
+pub struct Startup {
+    /// Where the session is restored from and autosaved to, `None` to do
+    /// neither.
+    pub session: Option<PathBuf>,
+    /// Restart the forwards of the last session, when it asks for it.
+    pub restart: bool,
+}
+
+impl Default for Startup {
+    fn default() -> Self {
+        Self {
+            session: session_path(),
+            restart: true,
+        }
+    }
+}
+
+impl Startup {
+    /// Reads the command line: `--no-restart` restores the last session
+    /// with its forwards stopped, `--no-session` starts empty and keeps
+    /// no session. Recent files are kept either way.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        let mut startup = Self::default();
+        for arg in args {
+            match arg.as_str() {
+                "--no-restart" => startup.restart = false,
+                "--no-session" => startup.session = None,
+                arg => warn!("unknown option {}", arg),
+            }
+        }
+        startup
+    }
+}
+
+#[derive(Debug,Default,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Deployment {
+    #[serde(default)]
+    pub mappings: Vec<Mapping>,
+    #[serde(default)]
+    pub forwarded: bool,
+    #[serde(default)]
+    pub watch: bool,
+    #[serde(default)]
+    pub pool: usize,
+    #[serde(default)]
+    pub balance: Balance,
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+impl Deployment {
+    pub fn spec(&self,context: &str,mapping: &Mapping) -> ForwardSpec {
+        ForwardSpec {
+            port: mapping.local,
+            bind: mapping.bind,
+            container: mapping.container.clone(),
+            remote: mapping.remote.clone(),
+            watch: self.watch,
+            pool: self.pool,
+            balance: self.balance,
+            pin: self.pin.clone(),
+            context: Some(context.to_string()).filter(|context| !context.is_empty()),
+        }
+    }
+}
+
+/// A local port forwarded to a port of the deployment's pods.
+#[derive(Debug,Default,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct Mapping {
+    pub local: u16,
+    #[serde(default)]
+    pub bind: Bind,
+    #[serde(default)]
+    pub container: Option<String>,
+    #[serde(default)]
+    pub remote: Option<RemotePort>,
+}
+
+impl Display for Mapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let remote = self.remote.as_ref().map(|remote| remote.to_string()).unwrap_or("first port".into());
+        match self.bind {
+            Bind::Loopback => write!(f, "{}", self.local)?,
+            Bind::LoopbackV6 | Bind::Address(IpAddr::V6(_)) => write!(f, "[{}]:{}", self.bind, self.local)?,
+            bind => write!(f, "{}:{}", bind, self.local)?,
+        }
+        match &self.container {
+            Some(container) => write!(f, " -> {}/{}", container, remote),
+            None => write!(f, " -> {}", remote),
+        }
+    }
+}
+
+#[derive(Debug,Default,Clone,PartialEq,Serialize,Deserialize)]
+pub struct NamespaceHistory {
+    #[serde(default)]
+    pub favourites: Vec<String>,
+    /// Most recent first.
+    #[serde(default)]
+    pub recent: Vec<String>,
+}
+
+impl NamespaceHistory {
+    const RECENT: usize = 10;
+
+    pub fn used(&mut self,namespace: &str) {
+        if namespace.is_empty() {
+            return;
         }
+        self.recent.retain(|recent| recent != namespace);
+        self.recent.insert(0, namespace.to_string());
+        self.recent.truncate(Self::RECENT);
+    }
+
+    pub fn is_favourite(&self,namespace: &str) -> bool {
+        self.favourites.iter().any(|favourite| favourite == namespace)
+    }
 
-        let result = file?.write_all(serde_json::to_string_pretty(self)?.as_bytes()).await;
-        if let Err(e)  = &result{
-            error!("{}",e);
-            return Err(Box::new(PFError::WriteConfigBad));
+    pub fn toggle_favourite(&mut self,namespace: &str) {
+        if self.is_favourite(namespace) {
+            self.favourites.retain(|favourite| favourite != namespace);
+        } else {
+            self.favourites.push(namespace.to_string());
         }
+    }
+}
+
+impl DeploymentConfig {
+
+    pub fn namespace(&self,context: &str,namespace: &str) -> Option<&HashMap<String,Deployment>> {
+        self.clusters.get(context)?.get(namespace)
+    }
+
+    pub fn namespace_mut(&mut self,context: &str,namespace: &str) -> &mut HashMap<String,Deployment> {
+        self.clusters.entry(context.to_string()).or_default().entry(namespace.to_string()).or_default()
+    }
+
+    pub fn deployment_mut(&mut self,(context,namespace,name): &Target) -> Option<&mut Deployment> {
+        self.clusters.get_mut(context)?.get_mut(namespace)?.get_mut(name)
+    }
+
+    /// Reads a saved config in the format of its extension, upgrading files
+    /// saved by older versions. A file that can't be parsed is reported and
+    /// leaves the config as it was.
+    pub fn load(&mut self,path: PathBuf) -> Result<()> {
+        let cfg = fs::read_to_string(path.as_path()).map_err(|e| PFError::LoadConfig(Arc::new(e)))?;
+        *self = Self::upgrade(Format::from_path(&path).parse(&cfg)?)?;
 
         Ok(())
     }
+
+    /// Reads a parsed config saved by any version up to this one. A
+    /// version that is not a number is a parse error, a newer one is
+    /// refused.
+    fn upgrade(config: Value) -> Result<Self> {
+        let parse = |e: serde_json::Error| PFError::ConfigParse(Arc::new(e));
+        let Value::Object(mut config) = config else {
+            return Err(parse(serde::de::Error::custom("expected a JSON object")));
+        };
+        let version = match config.get("version") {
+            None => 0,
+            Some(version) => version.as_u64()
+                .ok_or_else(|| parse(serde::de::Error::custom(format!("version {} is not a number", version))))?,
+        };
+        if version > VERSION {
+            return Err(PFError::ConfigVersion(version.to_string()));
+        }
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut config);
+        }
+        serde_json::from_value(Value::Object(config)).map_err(parse)
+    }
+
+    /// Writes the config in the format of the file extension, replacing
+    /// the file at once and keeping the previous one as a backup.
+    pub async fn save(&self,path: PathBuf) -> Result<()>{
+        let mut config = serde_json::to_value(self).map_err(|e| PFError::WriteConfig(Arc::new(e)))?;
+        config["version"] = VERSION.into();
+        let content = Format::from_path(&path).write(&config)?;
+        write_atomic(&path, &content).await
+    }
 }
 
 
@@ -70,28 +467,121 @@ impl DeploymentConfig {
 pub struct DataConfig {
     pub destination: PathBuf,
     pub search_value: String,
+    pub contexts: Vec<String>,
+    pub current_context: Option<String>,
     pub current_namespace: String,
     pub current_deployment: String,
     pub current_entries: usize,
     pub current_succeed: usize,
+    pub current_mappings: Vec<Mapping>,
     pub current_port: String,
-    pub list_deployment_error: String,
+    pub current_bind: String,
+    pub current_ports: Vec<ContainerPort>,
+    pub current_container: Option<String>,
+    pub current_remote: String,
+    pub current_watch: bool,
+    pub current_pool: String,
+    pub current_balance: Balance,
+    pub current_pin: String,
+    pub list_deployment_error: Option<PFError>,
+    /// Why the config could not be loaded or saved.
+    pub config_error: Option<PFError>,
     pub check_forwarded: bool,
+    /// List every namespace of the context rather than the current one.
+    pub all_namespaces: bool,
+    pub current_profile: Option<String>,
+    /// Name typed for a new profile.
+    pub profile_input: String,
+    /// Targets of the profile last started that had no mappings to start.
+    pub profile_skipped: Vec<String>,
 }
 
 impl DataConfig {
     pub fn clear(&mut self) {
         self.search_value = "".to_string();
+        self.current_mappings.clear();
         self.current_port = "".to_string();
+        self.current_bind = "".to_string();
+        self.current_ports.clear();
+        self.current_container = None;
+        self.current_remote = "".to_string();
+        self.current_watch = false;
+        self.current_pool = "".to_string();
+        self.current_balance = Balance::default();
+        self.current_pin = "".to_string();
         self.current_namespace = "".to_string();
         self.current_deployment = "".to_string();
         self.current_entries = 0;
         self.current_succeed = 0;
-        self.list_deployment_error = "".to_string();
+        self.list_deployment_error = None;
         self.check_forwarded = false;
     }
 }
 
  
 
- 
\ No newline at end of file
+ 
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn deployment(config: &DeploymentConfig,namespace: &str,name: &str) -> Deployment {
+        config.clusters[""][namespace][name].clone()
+    }
+
+    #[test]
+    fn unversioned_baseline() {
+        let config = DeploymentConfig::upgrade(json!({
+            "deployments": { "default": { "web": { "port": 8080, "forwarded": 1 } } }
+        })).unwrap();
+        assert_eq!(deployment(&config, "default", "web"), Deployment {
+            mappings: vec![Mapping { local: 8080, ..Default::default() }],
+            forwarded: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn unversioned_without_port() {
+        let config = DeploymentConfig::upgrade(json!({
+            "deployments": { "default": { "web": { "port": 0, "forwarded": 0 } } }
+        })).unwrap();
+        assert_eq!(deployment(&config, "default", "web"), Deployment::default());
+    }
+
+    #[test]
+    fn unversioned_by_context() {
+        let config = DeploymentConfig::upgrade(json!({
+            "deployments": { "default": { "web": { "port": 8080, "context": "staging", "remote": 80 } } }
+        })).unwrap();
+        let web = &config.clusters["staging"]["default"]["web"];
+        assert_eq!(web.mappings, vec![Mapping { local: 8080, remote: Some(RemotePort::Number(80)), ..Default::default() }]);
+    }
+
+    #[test]
+    fn current_version_is_kept() {
+        let config = DeploymentConfig::upgrade(json!({
+            "version": VERSION,
+            "clusters": { "": { "default": { "web": { "mappings": [{ "local": 8080 }], "forwarded": true } } } }
+        })).unwrap();
+        assert!(deployment(&config, "default", "web").forwarded);
+    }
+
+    #[test]
+    fn newer_version() {
+        match DeploymentConfig::upgrade(json!({ "version": VERSION + 1 })) {
+            Err(PFError::ConfigVersion(version)) => assert_eq!(version, (VERSION + 1).to_string()),
+            other => panic!("expected a version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn version_not_a_number() {
+        for version in [json!("abc"), json!(-1), json!(1.5)] {
+            let result = DeploymentConfig::upgrade(json!({ "version": version }));
+            assert!(matches!(result, Err(PFError::ConfigParse(_))), "{}: {:?}", version, result);
+        }
+    }
+}
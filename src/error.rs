@@ -1,11 +1,126 @@
+use std::{io, net::SocketAddr, sync::Arc};
+use kube::config::KubeconfigError;
+use kube::runtime::watcher;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+/// The error a variant wraps. Shared rather than boxed, so errors can be
+/// carried by messages, which are cloned.
+pub type Source = Arc<dyn std::error::Error + Send + Sync>;
+
+#[derive(Error, Debug, Clone)]
 pub enum PFError {
     #[error("Resource {0} Not Found")]
     ResourceNotFound(String),
-    #[error("Write Config Failed")]
-    WriteConfigBad,
-    #[error("Load Config Failed")]
-    LoadConfigBad,
-}
\ No newline at end of file
+    #[error("No Ready Pod, {0}")]
+    PodNotReady(String),
+    #[error("Invalid Label Selector: {0}")]
+    InvalidSelector(String),
+    #[error("Missing Permission: {0}")]
+    Unauthorized(String),
+    #[error("Bind {addr} Failed: {source}")]
+    BindFailed { addr: SocketAddr, source: Source },
+    #[error("Port {0} In Use")]
+    PortInUse(SocketAddr),
+    #[error("Kubeconfig Not Usable: {0}")]
+    KubeconfigMissing(#[source] Source),
+    #[error("API Server Unreachable: {0}")]
+    ApiUnreachable(#[source] Source),
+    #[error("API Request Failed: {0}")]
+    Api(#[source] Source),
+    #[error("Connection Failed: {0}")]
+    Connection(#[source] Source),
+    #[error("Load Config Failed: {0}")]
+    LoadConfig(#[source] Source),
+    #[error("Invalid Config: {0}")]
+    ConfigParse(#[source] Source),
+    #[error("Config Version {0} Is Newer Than This App")]
+    ConfigVersion(String),
+    #[error("Write Config Failed: {0}")]
+    WriteConfig(#[source] Source),
+    #[error("Stopped")]
+    Closed,
+}
+
+impl PFError {
+    /// What the user can do about the error.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            PFError::ResourceNotFound(_) => Some("it may have been deleted, reload the namespace"),
+            PFError::PodNotReady(_) => Some("wait for the pods to become ready, or forward with watch pods"),
+            PFError::InvalidSelector(_) => Some("fix the selector of the resource in the cluster"),
+            PFError::Unauthorized(_) => Some("ask a cluster admin for the permission, or switch context"),
+            PFError::BindFailed { .. } => Some("pick another bind address"),
+            PFError::PortInUse(_) => Some("another program listens there, pick another local port"),
+            PFError::KubeconfigMissing(_) => Some("set KUBECONFIG or write ~/.kube/config"),
+            PFError::ApiUnreachable(_) => Some("check the network, a VPN and the cluster address"),
+            PFError::ConfigParse(_) => Some("fix the file or load another one"),
+            PFError::ConfigVersion(_) => Some("update the app to load it"),
+            PFError::LoadConfig(_) | PFError::WriteConfig(_) => Some("check the path and its permissions"),
+            PFError::Api(_) | PFError::Connection(_) | PFError::Closed => None,
+        }
+    }
+
+    /// Whether trying again may succeed without the user changing anything
+    /// in this app first.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, PFError::InvalidSelector(_) | PFError::ConfigParse(_) | PFError::ConfigVersion(_) | PFError::Closed)
+    }
+
+    /// Classifies a failed bind of a local listener.
+    pub fn bind(addr: SocketAddr,e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::AddrInUse => PFError::PortInUse(addr),
+            _ => PFError::BindFailed { addr, source: Arc::new(e) },
+        }
+    }
+}
+
+/// Errors are equal when they read the same, their sources can't be compared.
+impl PartialEq for PFError {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for PFError {}
+
+impl From<kube::Error> for PFError {
+    fn from(e: kube::Error) -> Self {
+        match e {
+            kube::Error::Api(response) if response.code == 401 || response.code == 403 => PFError::Unauthorized(response.message),
+            kube::Error::Api(response) if response.code == 404 => PFError::ResourceNotFound(response.message),
+            kube::Error::Auth(e) => PFError::Unauthorized(e.to_string()),
+            kube::Error::InferConfig(e) => PFError::KubeconfigMissing(Arc::new(e)),
+            kube::Error::HyperError(_) | kube::Error::Service(_) => PFError::ApiUnreachable(Arc::new(e)),
+            e => PFError::Api(Arc::new(e)),
+        }
+    }
+}
+
+impl From<watcher::Error> for PFError {
+    fn from(e: watcher::Error) -> Self {
+        match e {
+            watcher::Error::InitialListFailed(e) | watcher::Error::WatchStartFailed(e) | watcher::Error::WatchFailed(e) => e.into(),
+            watcher::Error::WatchError(response) => kube::Error::Api(response).into(),
+            e => PFError::Api(Arc::new(e)),
+        }
+    }
+}
+
+impl From<KubeconfigError> for PFError {
+    fn from(e: KubeconfigError) -> Self {
+        PFError::KubeconfigMissing(Arc::new(e))
+    }
+}
+
+impl From<io::Error> for PFError {
+    fn from(e: io::Error) -> Self {
+        PFError::Connection(Arc::new(e))
+    }
+}
+
+impl<T> From<tokio::sync::mpsc::error::SendError<T>> for PFError {
+    fn from(_: tokio::sync::mpsc::error::SendError<T>) -> Self {
+        PFError::Closed
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use iced::window;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::warn;
+
+use crate::{Message, PFError, Result};
+
+/// The kubeconfig context, namespace and reference of what is forwarded. An
+/// empty context stands for the current one.
+pub type Target = (String, String, String);
+
+/// A forward is identified by its target and by its local port.
+pub type ForwardKey = (Target, u16);
+
+/// Lifecycle of a single forward.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum ForwardState {
+    /// Looking up the deployment and a pod to forward to.
+    Resolving,
+    /// Binding the local listener.
+    Binding,
+    /// Accepting connections.
+    Listening,
+    /// Listening, but the last upstream connection failed.
+    Degraded(PFError),
+    /// Lost the pod and retrying, with the attempt number.
+    Reconnecting(u32),
+    Failed(PFError),
+    #[default]
+    Stopped,
+}
+
+impl ForwardState {
+    /// Whether the forward task has returned in this state.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, ForwardState::Failed(_) | ForwardState::Stopped)
+    }
+
+    /// Ranks states so a deployment shows the worst state of its forwards.
+    fn severity(&self) -> u8 {
+        match self {
+            ForwardState::Stopped => 0,
+            ForwardState::Listening => 1,
+            ForwardState::Resolving | ForwardState::Binding => 2,
+            ForwardState::Reconnecting(_) => 3,
+            ForwardState::Degraded(_) => 4,
+            ForwardState::Failed(_) => 5,
+        }
+    }
+}
+
+impl Display for ForwardState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForwardState::Resolving => write!(f, "Resolving"),
+            ForwardState::Binding => write!(f, "Binding"),
+            ForwardState::Listening => write!(f, "Listening"),
+            ForwardState::Degraded(e) => write!(f, "Degraded: {}", e),
+            ForwardState::Reconnecting(attempt) => write!(f, "Reconnecting (attempt {})", attempt),
+            ForwardState::Failed(e) => write!(f, "Failed: {}", e),
+            ForwardState::Stopped => write!(f, "Stopped"),
+        }
+    }
+}
+
+/// Sends state changes of one forward back to its window as
+/// [`Message::ForwardStatus`]. Repeated states are only sent once.
+#[derive(Clone)]
+pub struct Reporter {
+    id: window::Id,
+    key: ForwardKey,
+    handle: u64,
+    sender: mpsc::Sender<Message>,
+    last: Arc<Mutex<Option<ForwardState>>>,
+}
+
+impl Reporter {
+    pub fn new(id: window::Id, key: ForwardKey, handle: u64, sender: mpsc::Sender<Message>) -> Self {
+        Self {
+            id,
+            key,
+            handle,
+            sender,
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn report(&self, state: ForwardState) {
+        let mut last = self.last.lock().unwrap();
+        if last.as_ref() == Some(&state) {
+            return;
+        }
+        *last = Some(state.clone());
+
+        let _ = self.sender.clone().try_send(Message::ForwardStatus {
+            id: self.id,
+            key: self.key.clone(),
+            handle: self.handle,
+            state,
+        });
+    }
+}
+
+/// Exponential backoff between reconnect attempts, from half a second up
+/// to thirty seconds.
+#[derive(Debug, Default, Clone)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    const INITIAL: Duration = Duration::from_millis(500);
+    const MAX: Duration = Duration::from_secs(30);
+
+    /// Returns the number of the next attempt and how long to wait before it.
+    pub fn next(&mut self) -> (u32, Duration) {
+        let delay = Self::INITIAL
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(Self::MAX);
+        self.attempt += 1;
+        (self.attempt, delay)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+type Connect<S> = Arc<dyn Fn() -> BoxFuture<'static, Result<S>> + Send + Sync>;
+
+/// Keeps up to `size` upstream sessions opened ahead of time, so that a new
+/// client connection does not wait for the session handshake. Each session
+/// serves a single connection; the pool reopens one in the background as
+/// soon as one is taken, up to `size` at once. A slot whose session fails
+/// to open backs off before retrying. Dropping the pool stops refilling it.
+pub struct SessionPool<S> {
+    sessions: Mutex<tokio::sync::mpsc::Receiver<(Instant, S)>>,
+    connect: Connect<S>,
+    _guard: DropGuard,
+}
+
+impl<S: Send + 'static> SessionPool<S> {
+    /// Sessions idle for longer than this may have been closed by the
+    /// server or by a load balancer in front of it, which commonly drop
+    /// idle connections after a minute, so they are dropped instead of
+    /// handed out.
+    const MAX_IDLE: Duration = Duration::from_secs(20);
+
+    pub fn new<F, Fut>(size: usize, connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<S>> + Send + 'static,
+    {
+        let connect: Connect<S> = Arc::new(move || connect().boxed());
+        let (sender, receiver) = tokio::sync::mpsc::channel(size.max(1));
+        let token = CancellationToken::new();
+
+        for _ in 0..size {
+            let connect = connect.clone();
+            let token = token.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                // each slot backs off on its own, so a failing upstream is
+                // retried less and less often instead of every second
+                let mut backoff = Backoff::default();
+                loop {
+                    let permit = tokio::select! {
+                        _ = token.cancelled() => return,
+                        permit = sender.reserve() => match permit {
+                            Ok(permit) => permit,
+                            Err(_) => return,
+                        },
+                    };
+                    let session = tokio::select! {
+                        _ = token.cancelled() => return,
+                        session = connect() => session,
+                    };
+                    match session {
+                        Ok(session) => {
+                            backoff.reset();
+                            permit.send((Instant::now(), session));
+                        }
+                        Err(e) => {
+                            drop(permit);
+                            let (attempt, delay) = backoff.next();
+                            warn!("opening a pooled session, attempt {}: {}", attempt, e);
+                            tokio::select! {
+                                _ = token.cancelled() => return,
+                                _ = tokio::time::sleep(delay) => {}
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        Self {
+            sessions: Mutex::new(receiver),
+            connect,
+            _guard: token.drop_guard(),
+        }
+    }
+
+    /// Takes a warm session, or opens one when the pool is empty.
+    pub async fn get(&self) -> Result<S> {
+        let warm = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let mut warm = None;
+            while let Ok((opened, session)) = sessions.try_recv() {
+                if opened.elapsed() < Self::MAX_IDLE {
+                    warm = Some(session);
+                    break;
+                }
+            }
+            warm
+        };
+
+        match warm {
+            Some(session) => Ok(session),
+            None => (self.connect)().await,
+        }
+    }
+}
+
+/// How a forward spreads its connections over the pods of a deployment.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    /// Connections from the same client address go to the same pod.
+    ClientIp,
+}
+
+impl Balance {
+    pub const ALL: [Balance; 3] = [Balance::RoundRobin, Balance::LeastConnections, Balance::ClientIp];
+}
+
+impl Display for Balance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Balance::RoundRobin => write!(f, "round robin"),
+            Balance::LeastConnections => write!(f, "least connections"),
+            Balance::ClientIp => write!(f, "client ip"),
+        }
+    }
+}
+
+/// The port a forward connects to in the pod: a number, which does not
+/// have to be declared in the pod spec, or the name of a container port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RemotePort {
+    Number(u16),
+    Name(String),
+}
+
+impl RemotePort {
+    /// Parses user input, `None` when it is empty.
+    pub fn parse(input: &str) -> Option<RemotePort> {
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+        Some(match input.parse::<u16>() {
+            Ok(port) => RemotePort::Number(port),
+            Err(_) => RemotePort::Name(input.to_string()),
+        })
+    }
+}
+
+impl Display for RemotePort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemotePort::Number(port) => write!(f, "{}", port),
+            RemotePort::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The local address a forward listens on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bind {
+    /// 127.0.0.1
+    #[default]
+    Loopback,
+    /// ::1
+    LoopbackV6,
+    /// Both 127.0.0.1 and ::1, for tools that resolve localhost to either.
+    DualStack,
+    /// 0.0.0.0, every IPv4 interface.
+    Any,
+    /// A specific interface.
+    Address(IpAddr),
+}
+
+impl Bind {
+    /// Parses user input, loopback when it is empty and `None` when it is
+    /// neither a preset nor an IP address.
+    pub fn parse(input: &str) -> Option<Bind> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Some(Bind::Loopback);
+        }
+        if input == "dual-stack" {
+            return Some(Bind::DualStack);
+        }
+        let ip = input.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok()?;
+        Some(match ip {
+            IpAddr::V4(v4) if v4 == Ipv4Addr::LOCALHOST => Bind::Loopback,
+            IpAddr::V6(v6) if v6 == Ipv6Addr::LOCALHOST => Bind::LoopbackV6,
+            IpAddr::V4(v4) if v4.is_unspecified() => Bind::Any,
+            ip => Bind::Address(ip),
+        })
+    }
+
+    pub fn addrs(&self, port: u16) -> Vec<SocketAddr> {
+        let ips: Vec<IpAddr> = match self {
+            Bind::Loopback => vec![Ipv4Addr::LOCALHOST.into()],
+            Bind::LoopbackV6 => vec![Ipv6Addr::LOCALHOST.into()],
+            Bind::DualStack => vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+            Bind::Any => vec![Ipv4Addr::UNSPECIFIED.into()],
+            Bind::Address(ip) => vec![*ip],
+        };
+        ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect()
+    }
+
+    /// Whether other machines may reach the forward.
+    pub fn is_exposed(&self) -> bool {
+        self.addrs(0).iter().any(|addr| !addr.ip().is_loopback())
+    }
+}
+
+impl Display for Bind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bind::Loopback => write!(f, "127.0.0.1"),
+            Bind::LoopbackV6 => write!(f, "::1"),
+            Bind::DualStack => write!(f, "dual-stack"),
+            Bind::Any => write!(f, "0.0.0.0"),
+            Bind::Address(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+/// What a forward listens on and how it follows the deployment's pods.
+#[derive(Debug, Default, Clone)]
+pub struct ForwardSpec {
+    pub port: u16,
+    pub bind: Bind,
+    /// Container whose ports `remote` is looked up in, any when `None`.
+    pub container: Option<String>,
+    /// Port in the pod, the container's first port when `None`.
+    pub remote: Option<RemotePort>,
+    /// Watch the pod of a forward to a single pod and fail over as soon as
+    /// it goes away, rather than on the next failing connection. Forwards
+    /// spreading over several pods always watch them, see `Kind::spreads`.
+    pub watch: bool,
+    /// Number of upstream sessions kept open ahead of time, 0 to open one
+    /// per connection.
+    pub pool: usize,
+    pub balance: Balance,
+    /// Restricts a StatefulSet forward to one ordinal, or a DaemonSet
+    /// forward to the pod on one node.
+    pub pin: Option<String>,
+    /// Kubeconfig context of the cluster, the current one when `None`.
+    pub context: Option<String>,
+}
+
+/// Handle to a running forward. Cancelling its token unbinds the local
+/// listener and closes every live connection.
+#[derive(Debug, Clone)]
+pub struct ForwardHandle {
+    pub id: u64,
+    pub spec: ForwardSpec,
+    token: CancellationToken,
+}
+
+impl ForwardHandle {
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+}
+
+/// Registry of the forwards running for one window, along with the last
+/// known state of every forward, running or not.
+#[derive(Debug, Default, Clone)]
+pub struct Forwards {
+    next_id: u64,
+    handles: HashMap<ForwardKey, ForwardHandle>,
+    states: HashMap<ForwardKey, ForwardState>,
+}
+
+impl Forwards {
+    /// Registers a new forward for `key`, stopping the one it replaces.
+    pub fn start(&mut self, key: ForwardKey, spec: ForwardSpec) -> ForwardHandle {
+        self.stop(&key);
+        self.next_id += 1;
+        let handle = ForwardHandle {
+            id: self.next_id,
+            spec,
+            token: CancellationToken::new(),
+        };
+        self.handles.insert(key.clone(), handle.clone());
+        self.states.insert(key, ForwardState::Resolving);
+        handle
+    }
+
+    pub fn stop(&mut self, key: &ForwardKey) -> Option<ForwardHandle> {
+        let handle = self.handles.remove(key)?;
+        handle.stop();
+        self.states.insert(key.clone(), ForwardState::Stopped);
+        Some(handle)
+    }
+
+    pub fn stop_deployment(&mut self, target: &Target) {
+        for handle in self.running(target) {
+            self.stop(&(target.clone(), handle.spec.port));
+        }
+    }
+
+    /// Stops the forward and forgets its state, e.g. when its mapping is removed.
+    pub fn forget(&mut self, key: &ForwardKey) {
+        self.stop(key);
+        self.states.remove(key);
+    }
+
+    pub fn stop_all(&mut self) {
+        for (_, handle) in self.handles.drain() {
+            handle.stop();
+        }
+        self.states.clear();
+    }
+
+    /// Records a state reported by the forward `id`. Returns false when `id`
+    /// no longer identifies the registered forward, e.g. it was replaced.
+    /// Terminal states unregister the forward.
+    pub fn update(&mut self, key: &ForwardKey, id: u64, state: ForwardState) -> bool {
+        match self.handles.get(key) {
+            Some(handle) if handle.id == id => {
+                if state.is_terminal() {
+                    self.handles.remove(key);
+                }
+                self.states.insert(key.clone(), state);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn state(&self, key: &ForwardKey) -> ForwardState {
+        self.states.get(key).cloned().unwrap_or_default()
+    }
+
+    /// The worst state among the forwards of a deployment.
+    pub fn deployment_state(&self, target: &Target) -> ForwardState {
+        self.states
+            .iter()
+            .filter(|((t, _), _)| t == target)
+            .map(|(_, state)| state)
+            .max_by_key(|state| state.severity())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Handles of the running forwards of a deployment.
+    pub fn running(&self, target: &Target) -> Vec<ForwardHandle> {
+        self.handles
+            .iter()
+            .filter(|((t, _), _)| t == target)
+            .map(|(_, handle)| handle.clone())
+            .collect()
+    }
+}
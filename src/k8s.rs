@@ -1,16 +1,36 @@
-use std::fmt::Debug;
-use std::net::SocketAddr;
+use std::any::{Any, TypeId};
+use std::fmt::{Debug, Display};
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use futures::{StreamExt, TryStreamExt};
-use k8s_openapi::api::apps::v1::Deployment;
-use k8s_openapi::api::core::v1::Pod;
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
-use kube::api::ListParams;
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet};
+use k8s_openapi::api::authorization::v1::{ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec};
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::{Container, Namespace, Pod, PodTemplateSpec, Service, ServicePort};
+use k8s_openapi::api::discovery::v1::EndpointSlice;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, Time};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{ListParams, PostParams, Portforwarder};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::core::{Selector, SelectorExt};
+use kube::runtime::reflector::{ObjectRef, Store};
+use kube::runtime::{reflector, watcher, WatchStreamExt};
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpListener,
+    sync::{mpsc, Mutex, Notify},
 };
 use tokio_stream::wrappers::TcpListenerStream;
-use crate::{PFError, Result};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+use crate::forward::{Backoff, Balance, ForwardSpec, RemotePort, Reporter};
+use crate::{ForwardState, PFError, Result, SessionPool};
 
 
 #[derive(Clone)]
@@ -19,6 +39,7 @@ pub struct PFPod {
     pub namespace: String,
     pub port: u16,
     pub forward: u16,
+    created: Option<Time>,
     client: kube::Client,
 }
 
@@ -28,152 +49,1450 @@ impl Debug for PFPod {
     }
 }
 
+/// A port declared by a container of a pod template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerPort {
+    pub container: String,
+    pub name: Option<String>,
+    pub port: u16,
+}
+
+impl ContainerPort {
+    fn list(containers: &[Container]) -> Vec<ContainerPort> {
+        containers.iter()
+            .flat_map(|container| container.ports.iter().flatten().map(|port| ContainerPort {
+                container: container.name.clone(),
+                name: port.name.clone(),
+                port: port.container_port as u16,
+            }))
+            .collect()
+    }
+
+    pub fn remote(&self) -> RemotePort {
+        match &self.name {
+            Some(name) => RemotePort::Name(name.clone()),
+            None => RemotePort::Number(self.port),
+        }
+    }
+}
+
+impl Display for ContainerPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // service ports are not declared by a container
+        if !self.container.is_empty() {
+            write!(f, "{}/", self.container)?;
+        }
+        match &self.name {
+            Some(name) => write!(f, "{} ({})", name, self.port),
+            None => write!(f, "{}", self.port),
+        }
+    }
+}
+
+/// Why a pod cannot be forwarded to, `None` when it is running, ready and
+/// not being deleted. Like kubectl, the reason a container is waiting or
+/// terminated wins over the phase of the pod.
+fn not_ready_reason(pod: &Pod) -> Option<String> {
+    if pod.metadata.deletion_timestamp.is_some() {
+        return Some("Terminating".into());
+    }
+    let status = pod.status.as_ref();
+    let phase = status.and_then(|status| status.phase.clone()).unwrap_or("Unknown".into());
+    let ready = status
+        .and_then(|status| status.conditions.as_ref())
+        .and_then(|conditions| conditions.iter().find(|condition| condition.type_ == "Ready"))
+        .is_some_and(|condition| condition.status == "True");
+    if phase == "Running" && ready {
+        return None;
+    }
+    let container = status
+        .and_then(|status| status.container_statuses.as_ref())
+        .into_iter()
+        .flatten()
+        .filter(|container| !container.ready)
+        .find_map(|container| {
+            let state = container.state.as_ref()?;
+            state.waiting.as_ref().and_then(|waiting| waiting.reason.clone())
+                .or_else(|| state.terminated.as_ref().and_then(|terminated| terminated.reason.clone()))
+        });
+    Some(container.unwrap_or(match phase.as_str() {
+        "Running" => "NotReady".into(),
+        _ => phase,
+    }))
+}
+
+/// The error of a forward none of whose pods is ready, with the distinct
+/// reasons of the pods, e.g. "0/3 ready: ImagePullBackOff".
+fn pods_not_ready(pods: &[Arc<Pod>]) -> PFError {
+    let mut reasons: Vec<String> = pods.iter().filter_map(|pod| not_ready_reason(pod)).collect();
+    reasons.sort();
+    reasons.dedup();
+    PFError::PodNotReady(format!("0/{} ready: {}", pods.len(), reasons.join(", ")))
+}
+
+/// Resolves the port a forward connects to in a pod. A named port is looked
+/// up in the chosen container, or in every container when none is chosen.
+/// Without a remote port the first port of the container is used.
+fn resolve_port(containers: &[Container],spec: &ForwardSpec) -> Option<u16> {
+    let ports = ContainerPort::list(containers)
+        .into_iter()
+        .filter(|port| spec.container.as_ref().is_none_or(|container| &port.container == container));
+    match &spec.remote {
+        Some(RemotePort::Number(port)) => Some(*port),
+        Some(RemotePort::Name(name)) => ports
+            .into_iter()
+            .find(|port| port.name.as_ref() == Some(name))
+            .map(|port| port.port),
+        None => ports.into_iter().next().map(|port| port.port),
+    }
+}
+
 impl PFPod {
-    
-    pub async fn port_forward(&self,forward: u16) -> Result<()> {
+    fn new(pod: &Pod,client: kube::Client,spec: &ForwardSpec) -> Option<PFPod> {
+        let name = pod.metadata.name.clone()?;
+        let namespace = pod.metadata.namespace.clone()?;
+        let port = resolve_port(&pod.spec.as_ref()?.containers, spec)?;
+
+        Some(PFPod {
+            name,
+            namespace,
+            port,
+            forward: spec.port,
+            created: pod.metadata.creation_timestamp.clone(),
+            client,
+        })
+    }
+
+    async fn portforward(&self) -> Result<Portforwarder> {
         let api = kube::Api::<Pod>::namespaced(self.client.clone(), &self.namespace);
-        let addr = SocketAddr::from(([127, 0, 0, 1], forward));
-        let server = TcpListenerStream::new(TcpListener::bind(addr).await?)
-            .take_until(tokio::signal::ctrl_c())
+        Ok(api.portforward(self.name.as_str(),&[self.port]).await?)
+    }
+}
+
+type Pool = Arc<SessionPool<Portforwarder>>;
+
+/// A pod connections are spread over, with its session pool and the number
+/// of connections it currently serves.
+#[derive(Clone)]
+struct Backend {
+    pod: PFPod,
+    pool: Pool,
+    active: Arc<AtomicUsize>,
+}
+
+/// Counts a connection against its backend while alive.
+struct Active(Arc<AtomicUsize>);
+
+impl Active {
+    fn new(backend: &Backend) -> Self {
+        backend.active.fetch_add(1, Ordering::Relaxed);
+        Self(backend.active.clone())
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Backends {
+    pods: Vec<Backend>,
+    next: usize,
+    backoff: Backoff,
+}
+
+impl Backends {
+    fn pick(&mut self,balance: Balance,peer: Option<IpAddr>) -> Option<Backend> {
+        if self.pods.is_empty() {
+            return None;
+        }
+        let index = match balance {
+            Balance::RoundRobin => {
+                let index = self.next % self.pods.len();
+                self.next = self.next.wrapping_add(1);
+                index
+            }
+            Balance::LeastConnections => self.pods.iter()
+                .enumerate()
+                .min_by_key(|(_,backend)| backend.active.load(Ordering::Relaxed))
+                .map(|(index,_)| index)
+                .unwrap_or(0),
+            Balance::ClientIp => {
+                let mut hasher = DefaultHasher::new();
+                peer.hash(&mut hasher);
+                hasher.finish() as usize % self.pods.len()
+            }
+        };
+        Some(self.pods[index].clone())
+    }
+}
+
+/// The pods a forward spreads its connections over. Pods that fail are
+/// dropped from the set, and when none are left the set is resolved again
+/// from the deployment while the local listener stays bound.
+#[derive(Clone)]
+struct Upstream {
+    deployment: PFDeployment,
+    backends: Arc<Mutex<Backends>>,
+    /// Notified when pods are added to an empty set, e.g. by the watch.
+    added: Arc<Notify>,
+    /// Held by the connection resolving the pods again, the others wait
+    /// for it rather than resolving too.
+    resolving: Arc<Mutex<()>>,
+    spec: ForwardSpec,
+    token: CancellationToken,
+    reporter: Reporter,
+}
+
+impl Upstream {
+    fn new(deployment: PFDeployment,spec: ForwardSpec,token: CancellationToken,reporter: Reporter) -> Self {
+        Self {
+            deployment,
+            backends: Arc::new(Mutex::new(Backends::default())),
+            added: Arc::new(Notify::new()),
+            resolving: Arc::new(Mutex::new(())),
+            spec,
+            token,
+            reporter,
+        }
+    }
+
+    fn backend(&self,pod: PFPod) -> Backend {
+        let pool_pod = pod.clone();
+        let pool = Arc::new(SessionPool::new(self.spec.pool, move || {
+            let pod = pool_pod.clone();
+            async move { pod.portforward().await }
+        }));
+        Backend {
+            pod,
+            pool,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Replaces the pod set, keeping the pools of pods that stay. The newest
+    /// pods come first, so they are preferred.
+    fn set(&self,backends: &mut Backends,pods: Vec<PFPod>) {
+        let mut previous = std::mem::take(&mut backends.pods);
+        for pod in pods {
+            let backend = match previous.iter().position(|backend| backend.pod.name == pod.name) {
+                Some(index) => previous.swap_remove(index),
+                None => self.backend(pod),
+            };
+            backends.pods.push(backend);
+        }
+        backends.pods.sort_by(|a,b| b.pod.created.cmp(&a.pod.created).then_with(|| a.pod.name.cmp(&b.pod.name)));
+        if !backends.pods.is_empty() {
+            self.added.notify_waiters();
+        }
+    }
+
+    async fn remove(&self,name: &str) {
+        self.backends.lock().await.pods.retain(|backend| backend.pod.name != name);
+    }
+
+    async fn pick(&self,peer: Option<IpAddr>) -> Option<Backend> {
+        self.backends.lock().await.pick(self.spec.balance, peer)
+    }
+
+    async fn serve(&self,forward: u16) -> Result<()> {
+        self.reporter.report(ForwardState::Binding);
+        let mut listeners = Vec::new();
+        for addr in self.spec.bind.addrs(forward) {
+            let listener = TcpListener::bind(addr).await.map_err(|e| PFError::bind(addr, e))?;
+            listeners.push(TcpListenerStream::new(listener));
+        }
+        if self.spec.bind.is_exposed() {
+            warn!("{}/{} is forwarded on {}:{}, reachable beyond this machine",self.deployment.namespace,self.deployment.name,self.spec.bind,forward);
+        }
+        self.reporter.report(ForwardState::Listening);
+        let server = futures::stream::select_all(listeners)
+            .take_until(self.token.cancelled())
             .try_for_each(|conn| async {
-                if let Ok(peer_addr) = conn.peer_addr() {
-                    println!("{:?}", peer_addr);
-                }
-                let api = api.clone();
-                let pod_name = self.name.clone();
-                let port = self.port;
+                let peer = conn.peer_addr().ok().map(|peer_addr| peer_addr.ip());
+                debug!("connection to {}/{} from {:?}",self.deployment.namespace,self.deployment.name,peer);
+                let upstream = self.clone();
+                let target = format!("{}/{}",self.deployment.namespace,self.deployment.name);
+                let token = self.token.child_token();
                 tokio::spawn(async move{
-                    if let Err(e) = Self::handle_connection(&api,pod_name.as_str(),port,conn).await {
-                        eprintln!("{:?}", e);
+                    tokio::select! {
+                        _ = token.cancelled() => {}
+                        result = upstream.handle_connection(conn,peer) => {
+                            if let Err(e) = result {
+                                error!("forwarding a connection to {}: {}",target,e);
+                            }
+                        }
                     }
                 });
                 Ok(())
             });
-        
-        if let Err(e) =server.await {
-            eprintln!("{:?}", e);
+
+        server.await?;
+
+        Ok(())
+    }
+
+    async fn handle_connection(&self,mut conn: impl AsyncRead + AsyncWrite + Unpin,peer: Option<IpAddr>) -> Result<()>{
+        let (backend,mut forwarder) = loop {
+            let Some(backend) = self.pick(peer).await else {
+                if !self.failover(None).await {
+                    return Ok(());
+                }
+                continue;
+            };
+            match backend.pool.get().await {
+                Ok(forwarder) => break (backend,forwarder),
+                Err(e) => {
+                    self.reporter.report(ForwardState::Degraded(e));
+                    if !self.failover(Some(&backend.pod.name)).await {
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        self.backends.lock().await.backoff.reset();
+        self.reporter.report(ForwardState::Listening);
+
+        let _active = Active::new(&backend);
+        let Some(mut upstream_conn) = forwarder.take_stream(backend.pod.port) else {
+            return Err(PFError::ResourceNotFound(format!("Port {}", backend.pod.port)));
+        };
+        tokio::io::copy_bidirectional(&mut conn, &mut upstream_conn).await?;
+        forwarder.join().await.map_err(|e| PFError::Connection(Arc::new(e)))?;
+        debug!("connection to {}/{} done",self.deployment.namespace,self.deployment.name);
+        Ok(())
+    }
+
+    /// Drops the `failed` pod from the set. When no pod is left, resolves
+    /// the pods of the deployment again, backing off between attempts, until
+    /// a pod is found or the watch adds one. The set is not locked while
+    /// waiting, so other connections and the watch are not held up.
+    /// Returns false when the forward is stopped in the meantime.
+    async fn failover(&self,failed: Option<&str>) -> bool {
+        if let Some(failed) = failed {
+            self.remove(failed).await;
+        }
+        loop {
+            // created before the set is checked, so no addition is missed
+            let added = self.added.notified();
+            tokio::pin!(added);
+            if !self.backends.lock().await.pods.is_empty() {
+                return true;
+            }
+            let _resolving = tokio::select! {
+                _ = self.token.cancelled() => return false,
+                _ = &mut added => continue,
+                resolving = self.resolving.lock() => resolving,
+            };
+            let (attempt,delay) = {
+                let mut backends = self.backends.lock().await;
+                if !backends.pods.is_empty() {
+                    return true;
+                }
+                backends.backoff.next()
+            };
+            self.reporter.report(ForwardState::Reconnecting(attempt));
+            tokio::select! {
+                _ = self.token.cancelled() => return false,
+                _ = &mut added => continue,
+                _ = tokio::time::sleep(delay) => {}
+            }
+
+            match self.deployment.find_pods(&self.spec).await {
+                Ok(pods) => self.set(&mut *self.backends.lock().await, pods),
+                Err(e) => warn!("resolving pods of {:?}: {}", self.deployment, e),
+            }
+        }
+    }
+
+    /// Keeps the pod set up to date as pods of the target come and go or
+    /// turn ready and unready, resolving it again from the caches whenever
+    /// they change. A set left without pods makes connections wait in
+    /// `failover` until one is back.
+    async fn watch(&self,caches: PodCaches) {
+        let (pods,slices) = caches;
+        loop {
+            // created before resolving, so no change is missed
+            let pods_changed = pods.changed.notified();
+            let slices_changed = slices.as_ref().map(|slices| slices.changed.notified());
+            match self.deployment.find_pods(&self.spec).await {
+                Ok(found) => self.set(&mut *self.backends.lock().await, found),
+                Err(e) => {
+                    warn!("resolving pods of {:?}: {}", self.deployment, e);
+                    self.backends.lock().await.pods.clear();
+                }
+            }
+            match slices_changed {
+                Some(slices_changed) => tokio::select! {
+                    _ = pods_changed => {}
+                    _ = slices_changed => {}
+                },
+                None => pods_changed.await,
+            }
         }
+    }
+}
+
+/// Names of the kubeconfig contexts, with the files listed in `KUBECONFIG`
+/// merged, and the current context.
+pub fn kube_contexts() -> Result<(Vec<String>,Option<String>)> {
+    let kubeconfig = Kubeconfig::read()?;
+    let contexts = kubeconfig.contexts.into_iter().map(|context| context.name).collect();
+    Ok((contexts,kubeconfig.current_context))
+}
+
+/// Names of the namespaces of a cluster.
+pub async fn list_namespaces(context: Option<String>) -> Result<Vec<String>> {
+    let client = client(context.as_deref()).await?;
+    let api: kube::Api<Namespace> = kube::Api::all(client);
+    let list = api.list(&ListParams::default()).await?;
+    let mut namespaces: Vec<String> = list.items.into_iter().filter_map(|namespace| namespace.metadata.name).collect();
+    namespaces.sort();
+    Ok(namespaces)
+}
+
+/// Clients by kubeconfig context, the empty one standing for the current
+/// context. Creating a client reads the kubeconfig and negotiates TLS, so
+/// one is kept per context for the whole session.
+static CLIENTS: Lazy<Mutex<HashMap<String,kube::Client>>> = Lazy::new(Default::default);
+
+/// The client of a kubeconfig context, the current one when `None`.
+async fn client(context: Option<&str>) -> Result<kube::Client> {
+    let mut clients = CLIENTS.lock().await;
+    if let Some(client) = clients.get(context.unwrap_or_default()) {
+        return Ok(client.clone());
+    }
+    let client = match context {
+        None => kube::Client::try_default().await?,
+        Some(context) => {
+            let options = KubeConfigOptions {
+                context: Some(context.to_string()),
+                ..Default::default()
+            };
+            let config = kube::Config::from_kubeconfig(&options).await?;
+            kube::Client::try_from(config)?
+        }
+    };
+    clients.insert(context.unwrap_or_default().to_string(), client.clone());
+    Ok(client)
+}
+
+/// Reflector caches by kubeconfig context, namespace and kind of resource.
+/// Forwards and listings read their targets and pods from them rather than
+/// asking the API server every time one starts. A cache nobody holds any
+/// longer is dropped, see `Cache::UNUSED`.
+static CACHES: Lazy<Mutex<HashMap<CacheKey,Box<dyn Any + Send + Sync>>>> = Lazy::new(Default::default);
+
+/// The pods of a forward and, for a service, its endpoint slices.
+type PodCaches = (Cache<Pod>,Option<Cache<EndpointSlice>>);
+
+/// The context, namespace and kind of a `Cache`.
+type CacheKey = (String,String,TypeId);
+
+/// The resources of one kind in a namespace, or in every namespace when the
+/// namespace is empty, kept up to date by a watch.
+#[derive(Clone)]
+struct Cache<K: kube::Resource<DynamicType = ()> + 'static> {
+    store: Store<K>,
+    /// Notified whenever the watch changes the store.
+    changed: Arc<Notify>,
+    /// Counts the clones of the cache, the one in `CACHES` included.
+    users: Arc<()>,
+}
+
+impl<K> Cache<K>
+where
+    K: kube::Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + Debug + DeserializeOwned + Send + Sync + 'static,
+{
+    /// How often the watch checks whether the cache is still held. A cache
+    /// only `CACHES` holds at two checks in a row is dropped and its watch
+    /// stopped.
+    const UNUSED: std::time::Duration = std::time::Duration::from_secs(60);
+
+    /// The cache of a kind in a namespace of a context, listed the first
+    /// time it is asked for. The first listing has to succeed, the watch
+    /// following it retries on its own.
+    async fn get(context: Option<&str>,namespace: &str) -> Result<Self> {
+        Self::listing(context, namespace, None).await
+    }
+
+    /// Like `get`, sending the resources of the first listing to `items` as
+    /// they arrive when the cache is listed now.
+    async fn listing(context: Option<&str>,namespace: &str,items: Option<mpsc::Sender<K>>) -> Result<Self> {
+        let key = (context.unwrap_or_default().to_string(), namespace.to_string(), TypeId::of::<K>());
+        if let Some(cache) = CACHES.lock().await.get(&key).and_then(|cache| cache.downcast_ref::<Self>()) {
+            return Ok(cache.clone());
+        }
+        let client = client(context).await?;
+        let api: kube::Api<K> = match namespace {
+            "" => kube::Api::all(client),
+            namespace => kube::Api::namespaced(client, namespace),
+        };
+        let (store,writer) = reflector::store();
+        let mut events = reflector(writer, watcher(api, watcher::Config::default().page_size(PAGE_SIZE))).boxed();
+        while let Some(event) = events.try_next().await? {
+            match (event,&items) {
+                (watcher::Event::InitApply(item),Some(items)) => {
+                    // nobody listening does not stop the cache
+                    let _ = items.send(item).await;
+                }
+                (watcher::Event::InitDone,_) => break,
+                _ => {}
+            }
+        }
+
+        let mut caches = CACHES.lock().await;
+        // another forward may have listed it meanwhile
+        if let Some(cache) = caches.get(&key).and_then(|cache| cache.downcast_ref::<Self>()) {
+            return Ok(cache.clone());
+        }
+        let cache = Self { store, changed: Arc::new(Notify::new()), users: Arc::new(()) };
+        caches.insert(key.clone(), Box::new(cache.clone()));
+        let changed = cache.changed.clone();
+        let users = Arc::downgrade(&cache.users);
+        tokio::spawn(async move {
+            let mut events = events.default_backoff().boxed();
+            let mut unused = tokio::time::interval(Self::UNUSED);
+            let mut idle = false;
+            loop {
+                tokio::select! {
+                    event = events.next() => match event {
+                        Some(Ok(watcher::Event::Apply(_) | watcher::Event::Delete(_) | watcher::Event::InitDone)) => changed.notify_waiters(),
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => warn!("watching {}s in {}: {}", K::kind(&()), key.1, e),
+                        None => return,
+                    },
+                    _ = unused.tick() => {
+                        // checked under the lock, `get` clones under it too
+                        let mut caches = CACHES.lock().await;
+                        let held = users.strong_count() > 1;
+                        if !held && idle {
+                            caches.remove(&key);
+                            return;
+                        }
+                        idle = !held;
+                    }
+                }
+            }
+        });
+        Ok(cache)
+    }
+
+    fn named(&self,namespace: &str,name: &str) -> Option<Arc<K>> {
+        self.store.get(&ObjectRef::new(name).within(namespace))
+    }
+}
+
+/// A permission of the current identity, written the way `kubectl auth can-i`
+/// takes it: `create pods/portforward`, `list deployments.apps`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Permission {
+    verb: &'static str,
+    group: &'static str,
+    resource: &'static str,
+    subresource: &'static str,
+}
+
+impl Permission {
+    const LIST_DEPLOYMENTS: Self = Self { verb: "list", group: "apps", resource: "deployments", subresource: "" };
+    const WATCH_DEPLOYMENTS: Self = Self { verb: "watch", group: "apps", resource: "deployments", subresource: "" };
+    const LIST_PODS: Self = Self { verb: "list", group: "", resource: "pods", subresource: "" };
+    const WATCH_PODS: Self = Self { verb: "watch", group: "", resource: "pods", subresource: "" };
+    const PORT_FORWARD: Self = Self { verb: "create", group: "", resource: "pods", subresource: "portforward" };
+    const LIST_ENDPOINT_SLICES: Self = Self { verb: "list", group: "discovery.k8s.io", resource: "endpointslices", subresource: "" };
+    const WATCH_ENDPOINT_SLICES: Self = Self { verb: "watch", group: "discovery.k8s.io", resource: "endpointslices", subresource: "" };
+
+    /// What listing the targets of a namespace needs; the listing follows
+    /// deployments through a watch.
+    const LIST: [Self; 2] = [Self::LIST_DEPLOYMENTS, Self::WATCH_DEPLOYMENTS];
 
+    /// What forwarding to any kind needs. Pods and endpoint slices are
+    /// read through caches, which list and then watch them.
+    pub const FORWARD: [Self; 5] = [Self::LIST_PODS, Self::WATCH_PODS, Self::PORT_FORWARD, Self::LIST_ENDPOINT_SLICES, Self::WATCH_ENDPOINT_SLICES];
+
+    /// What forwarding to a kind needs; only services read endpoint slices.
+    pub fn forward(kind: Kind) -> &'static [Self] {
+        match kind {
+            Kind::Service => &Self::FORWARD,
+            _ => &Self::FORWARD[..3],
+        }
+    }
+
+    /// Asks the API server whether the current identity has the permission
+    /// in a namespace, in every namespace when empty.
+    async fn allowed(self,client: &kube::Client,namespace: &str) -> Result<bool> {
+        let api: kube::Api<SelfSubjectAccessReview> = kube::Api::all(client.clone());
+        let review = SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    namespace: Some(namespace.to_string()).filter(|namespace| !namespace.is_empty()),
+                    verb: Some(self.verb.to_string()),
+                    group: Some(self.group.to_string()),
+                    resource: Some(self.resource.to_string()),
+                    subresource: Some(self.subresource.to_string()).filter(|subresource| !subresource.is_empty()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let review = api.create(&PostParams::default(), &review).await?;
+        Ok(review.status.is_some_and(|status| status.allowed))
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.verb, self.resource)?;
+        if !self.group.is_empty() {
+            write!(f, ".{}", self.group)?;
+        }
+        if !self.subresource.is_empty() {
+            write!(f, "/{}", self.subresource)?;
+        }
         Ok(())
     }
+}
+
+/// Permissions granted so far, by context and namespace. A granted one is
+/// not asked for again during the session, a denied one is, as it may be
+/// granted meanwhile.
+static GRANTED: Lazy<Mutex<HashSet<(String,String,Permission)>>> = Lazy::new(Default::default);
+
+/// The permissions the current identity of a context lacks in a namespace.
+pub async fn missing_permissions(context: Option<String>,namespace: String,permissions: &'static [Permission]) -> Result<Vec<Permission>> {
+    let key = |permission: Permission| (context.clone().unwrap_or_default(),namespace.clone(),permission);
+    let unknown: Vec<Permission> = {
+        let granted = GRANTED.lock().await;
+        permissions.iter().copied().filter(|permission| !granted.contains(&key(*permission))).collect()
+    };
+    if unknown.is_empty() {
+        return Ok(Vec::new());
+    }
+    let client = client(context.as_deref()).await?;
+    let allowed = futures::future::try_join_all(unknown.iter().map(|permission| permission.allowed(&client, &namespace))).await?;
+    let mut granted = GRANTED.lock().await;
+    let mut missing = Vec::new();
+    for (permission,allowed) in unknown.into_iter().zip(allowed) {
+        match allowed {
+            true => {
+                granted.insert(key(permission));
+            }
+            false => missing.push(permission),
+        }
+    }
+    Ok(missing)
+}
+
+/// The kind of resource a forward targets.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    #[default]
+    Deployment,
+    StatefulSet,
+    DaemonSet,
+    ReplicaSet,
+    Job,
+    Pod,
+    Service,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 7] = [Kind::Deployment, Kind::StatefulSet, Kind::DaemonSet, Kind::ReplicaSet, Kind::Job, Kind::Pod, Kind::Service];
+
+    /// Splits a target reference, `kind/name` or the bare name of a
+    /// deployment as saved before other kinds were supported.
+    pub fn parse(reference: &str) -> (Kind, &str) {
+        reference.split_once('/')
+            .and_then(|(kind,name)| Kind::ALL.into_iter().find(|k| k.to_string() == kind).map(|kind| (kind,name)))
+            .unwrap_or((Kind::Deployment, reference))
+    }
+
+    /// What `ForwardSpec::pin` selects for this kind, if anything.
+    pub fn pin(&self) -> Option<&'static str> {
+        match self {
+            Kind::StatefulSet => Some("ordinal"),
+            Kind::DaemonSet => Some("node"),
+            _ => None,
+        }
+    }
+
+    /// Whether a forward to this kind may spread its connections over
+    /// several pods, which it does unless it targets or is pinned to one.
+    pub fn spreads(&self,pin: Option<&str>) -> bool {
+        *self != Kind::Pod && pin.is_none_or(|pin| pin.trim().is_empty())
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Deployment => write!(f, "deployment"),
+            Kind::StatefulSet => write!(f, "statefulset"),
+            Kind::DaemonSet => write!(f, "daemonset"),
+            Kind::ReplicaSet => write!(f, "replicaset"),
+            Kind::Job => write!(f, "job"),
+            Kind::Pod => write!(f, "pod"),
+            Kind::Service => write!(f, "service"),
+        }
+    }
+}
+
+/// Turns a LabelSelector into the selector pods are matched with. An
+/// empty selector, which matches every pod, and a malformed requirement are
+/// refused rather than selecting too broadly.
+fn label_selector(selector: &LabelSelector) -> Result<Selector> {
+    let invalid = PFError::InvalidSelector;
+    let labels = selector.match_labels.iter().flatten().count();
+    let mut expressions = 0;
+    for expression in selector.match_expressions.iter().flatten() {
+        let key = &expression.key;
+        let values = expression.values.as_deref().unwrap_or_default();
+        match expression.operator.as_str() {
+            "In" | "NotIn" if values.is_empty() => {
+                return Err(invalid(format!("{} {} without values", key, expression.operator)));
+            }
+            "Exists" | "DoesNotExist" if !values.is_empty() => {
+                return Err(invalid(format!("{} {} with values", key, expression.operator)));
+            }
+            "In" | "NotIn" | "Exists" | "DoesNotExist" => expressions += 1,
+            operator => return Err(invalid(format!("unknown operator {} on {}", operator, key))),
+        }
+    }
+    if labels + expressions == 0 {
+        return Err(invalid("empty selector".into()));
+    }
+    Selector::try_from(selector.clone()).map_err(|e| invalid(e.to_string()))
+}
+
+fn template_ports(template: &PodTemplateSpec) -> Vec<ContainerPort> {
+    template.spec.as_ref()
+        .map(|spec| ContainerPort::list(&spec.containers))
+        .unwrap_or_default()
+}
 
-    async fn handle_connection(
-        api: &kube::Api<Pod>, 
-        pod_name: &str,
-        port: u16,
-        mut conn: impl AsyncRead + AsyncWrite + Unpin
-    ) -> Result<()>{
-        let mut forwarder = api.portforward(pod_name,&[port]).await?;
-        let mut upstream_conn = forwarder.take_stream(port);
-        tokio::io::copy_bidirectional(&mut conn, &mut upstream_conn.unwrap()).await?;
-        // drop(upstream_conn);
-        forwarder.join().await?;
-        println!("port forwarding done");
+/// A namespaced resource running pods, that forwards can target.
+trait Workload: kube::Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + Debug + DeserializeOwned {
+    const KIND: Kind;
+
+    /// Selects the pods of the workload, `None` for a bare pod.
+    fn selector(&self) -> Option<LabelSelector>;
+
+    fn ports(&self) -> Vec<ContainerPort>;
+
+    /// Whether the workload shows up in the list of its namespace.
+    fn listed(&self) -> bool {
+        true
+    }
+}
+
+impl Workload for Deployment {
+    const KIND: Kind = Kind::Deployment;
+
+    fn selector(&self) -> Option<LabelSelector> {
+        self.spec.as_ref().map(|spec| spec.selector.clone())
+    }
+
+    fn ports(&self) -> Vec<ContainerPort> {
+        self.spec.as_ref().map(|spec| template_ports(&spec.template)).unwrap_or_default()
+    }
+}
+
+impl Workload for StatefulSet {
+    const KIND: Kind = Kind::StatefulSet;
+
+    fn selector(&self) -> Option<LabelSelector> {
+        self.spec.as_ref().map(|spec| spec.selector.clone())
+    }
+
+    fn ports(&self) -> Vec<ContainerPort> {
+        self.spec.as_ref().map(|spec| template_ports(&spec.template)).unwrap_or_default()
+    }
+}
+
+impl Workload for DaemonSet {
+    const KIND: Kind = Kind::DaemonSet;
+
+    fn selector(&self) -> Option<LabelSelector> {
+        self.spec.as_ref().map(|spec| spec.selector.clone())
+    }
+
+    fn ports(&self) -> Vec<ContainerPort> {
+        self.spec.as_ref().map(|spec| template_ports(&spec.template)).unwrap_or_default()
+    }
+}
+
+impl Workload for ReplicaSet {
+    const KIND: Kind = Kind::ReplicaSet;
+
+    fn selector(&self) -> Option<LabelSelector> {
+        self.spec.as_ref().map(|spec| spec.selector.clone())
+    }
+
+    fn ports(&self) -> Vec<ContainerPort> {
+        self.spec.as_ref().and_then(|spec| spec.template.as_ref()).map(template_ports).unwrap_or_default()
+    }
+
+    /// Replica sets owned by a deployment are forwarded through it.
+    fn listed(&self) -> bool {
+        self.metadata.owner_references.as_ref().is_none_or(|owners| owners.is_empty())
+    }
+}
+
+impl Workload for Job {
+    const KIND: Kind = Kind::Job;
+
+    fn selector(&self) -> Option<LabelSelector> {
+        self.spec.as_ref().and_then(|spec| spec.selector.clone())
+    }
+
+    fn ports(&self) -> Vec<ContainerPort> {
+        self.spec.as_ref().map(|spec| template_ports(&spec.template)).unwrap_or_default()
+    }
+}
+
+impl Workload for Pod {
+    const KIND: Kind = Kind::Pod;
+
+    fn selector(&self) -> Option<LabelSelector> {
+        None
+    }
+
+    fn ports(&self) -> Vec<ContainerPort> {
+        self.spec.as_ref().map(|spec| ContainerPort::list(&spec.containers)).unwrap_or_default()
+    }
+
+    /// Only pods nothing else manages are listed on their own.
+    fn listed(&self) -> bool {
+        self.metadata.owner_references.as_ref().is_none_or(|owners| owners.is_empty())
+    }
+}
+
+/// A change of the targets of a listed namespace.
+#[derive(Debug, Clone)]
+pub enum WorkloadEvent {
+    /// A page of the first listing.
+    Listed(Vec<PFDeployment>),
+    Applied(PFDeployment),
+    /// The namespace and reference of a target that is gone.
+    Deleted(String, String),
+}
+
+fn reference(kind: Kind,name: &str) -> String {
+    match kind {
+        Kind::Deployment => name.to_string(),
+        kind => format!("{}/{}", kind, name),
+    }
+}
+
+/// Builds the target of a listed resource, `None` when it is not listed.
+type Convert<K> = fn(K,kube::Client) -> Option<PFDeployment>;
+
+fn convert_workload<K: Workload>(workload: K,client: kube::Client) -> Option<PFDeployment> {
+    if !workload.listed() {
+        return None;
+    }
+    PFDeployment::from_workload(workload, client)
+}
+
+/// The label an EndpointSlice carries the name of its service in.
+const SERVICE_NAME_LABEL: &str = "kubernetes.io/service-name";
+
+/// Objects asked for per list call. Shared namespaces can hold thousands of
+/// workloads, which are better shown page by page than all at once.
+const PAGE_SIZE: u32 = 250;
+
+/// Follows the resources of one kind in a namespace, or in every namespace,
+/// through its cache, sending a `WorkloadEvent` for every target listed,
+/// applied or deleted. Changes are found by comparing resource versions
+/// with the ones seen last.
+struct Listing<K> {
+    client: kube::Client,
+    kind: Kind,
+    convert: Convert<K>,
+    /// Resource versions of the resources seen so far, by namespace and
+    /// name, targets or not.
+    seen: HashMap<(String,String),String>,
+    /// Namespaces and names of the targets sent so far.
+    sent: HashSet<(String,String)>,
+    /// Targets of the first listing not sent yet.
+    page: Vec<PFDeployment>,
+    events: mpsc::Sender<WorkloadEvent>,
+}
+
+type Sent = std::result::Result<(),mpsc::error::SendError<WorkloadEvent>>;
+
+impl<K> Listing<K>
+where
+    K: kube::Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + Debug + DeserializeOwned + Send + 'static,
+{
+    fn new(client: kube::Client,kind: Kind,convert: Convert<K>,events: mpsc::Sender<WorkloadEvent>) -> Self {
+        Self {
+            client,
+            kind,
+            convert,
+            seen: HashMap::new(),
+            sent: HashSet::new(),
+            page: Vec::new(),
+            events,
+        }
+    }
+
+    fn key(item: &K) -> ((String,String),String) {
+        let meta = item.meta();
+        ((meta.namespace.clone().unwrap_or_default(),meta.name.clone().unwrap_or_default()),meta.resource_version.clone().unwrap_or_default())
+    }
+
+    /// Adds a resource of the first listing to the page, sending the page
+    /// once full. Fails only when nobody listens to the events anymore.
+    async fn listed(&mut self,item: K) -> Sent {
+        let (key,version) = Self::key(&item);
+        if self.seen.insert(key.clone(), version).is_some() {
+            return Ok(());
+        }
+        if let Some(deployment) = (self.convert)(item, self.client.clone()) {
+            self.sent.insert(key);
+            self.page.push(deployment);
+            if self.page.len() >= PAGE_SIZE as usize {
+                self.events.send(WorkloadEvent::Listed(std::mem::take(&mut self.page))).await?;
+            }
+        }
         Ok(())
-    } 
+    }
+
+    /// Ends the first listing with the resources of the cache not listed
+    /// yet, e.g. when the cache was listed before.
+    async fn synced(&mut self,state: Vec<Arc<K>>) -> Sent {
+        for item in state.iter() {
+            if !self.seen.contains_key(&Self::key(item).0) {
+                self.listed((**item).clone()).await?;
+            }
+        }
+        self.events.send(WorkloadEvent::Listed(std::mem::take(&mut self.page))).await?;
+        self.changed(state).await
+    }
+
+    /// Sends the targets applied and deleted since the cache was last seen.
+    async fn changed(&mut self,state: Vec<Arc<K>>) -> Sent {
+        let mut current = HashSet::new();
+        for item in state {
+            let (key,version) = Self::key(&item);
+            current.insert(key.clone());
+            if self.seen.get(&key) == Some(&version) {
+                continue;
+            }
+            self.seen.insert(key.clone(), version);
+            match (self.convert)((*item).clone(), self.client.clone()) {
+                Some(deployment) => {
+                    self.sent.insert(key);
+                    self.events.send(WorkloadEvent::Applied(deployment)).await?;
+                }
+                // no longer a target, e.g. a replica set adopted by a
+                // deployment or a service switched to ExternalName
+                None if self.sent.remove(&key) => {
+                    self.events.send(WorkloadEvent::Deleted(key.0, reference(self.kind, &key.1))).await?;
+                }
+                None => {}
+            }
+        }
+        self.seen.retain(|key,_| current.contains(key));
+        let gone: Vec<(String,String)> = self.sent.iter().filter(|key| !current.contains(*key)).cloned().collect();
+        for key in gone {
+            self.sent.remove(&key);
+            self.events.send(WorkloadEvent::Deleted(key.0, reference(self.kind, &key.1))).await?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 pub struct PFDeployment {
     pub name: String,
     pub namespace: String,
+    pub kind: Kind,
+    /// Ports declared by the containers of the pod template, or the ports
+    /// of a service.
+    pub ports: Vec<ContainerPort>,
     selector: LabelSelector,
+    service_ports: Vec<ServicePort>,
     client: kube::Client
 }
 
 impl Debug for PFDeployment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"name is {} and namespace is {}",self.name,self.namespace)
+        write!(f,"{} {} in namespace {}",self.kind,self.name,self.namespace)
     }
 }
 
 impl PFDeployment {
-    pub async fn list_deployment(namespace: String) -> Result<Vec<PFDeployment>> {
-        let client = kube::Client::try_default().await?;
-        let api: kube::Api<Deployment> = kube::Api::namespaced(client.clone(), namespace.as_str());
-        let list = api.list(&ListParams::default()).await?;
-        let mut deployments = Vec::new();
-        for deployment in list.items {
-            let name = deployment.metadata.name.unwrap();
-            let namespace = deployment.metadata.namespace.unwrap();
-            let selector = deployment.spec.unwrap().selector;
-            deployments.push(PFDeployment { 
-                name,
-                namespace,
-                selector,
-                client: client.clone()
-            });
+    /// Lists the workloads and services of a namespace, or of every
+    /// namespace when `None`, sending them to `events` page by page, then
+    /// keeps sending their changes until `token` is cancelled. Only listing
+    /// deployments has to succeed, other kinds the user may not be allowed
+    /// to list are left out.
+    pub async fn list_deployment(context: Option<String>,namespace: Option<String>,events: mpsc::Sender<WorkloadEvent>,token: CancellationToken) -> Result<()> {
+        let client = client(context.as_deref()).await?;
+        let context = context.as_deref();
+        let namespace = namespace.as_deref();
+        if let Err(e) = Self::list_kind::<Deployment>(&client, context, namespace, Kind::Deployment, convert_workload, &events, &token).await {
+            // name the missing permissions rather than the bare API error
+            let mut missing = Vec::new();
+            for permission in Permission::LIST {
+                if let Ok(false) = permission.allowed(&client, namespace.unwrap_or_default()).await {
+                    missing.push(permission.to_string());
+                }
+            }
+            if !missing.is_empty() {
+                return Err(PFError::Unauthorized(missing.join(", ")));
+            }
+            return Err(e);
+        }
+        for kind in Kind::ALL.into_iter().skip(1) {
+            let listed = match kind {
+                Kind::StatefulSet => Self::list_kind::<StatefulSet>(&client, context, namespace, kind, convert_workload, &events, &token).await,
+                Kind::DaemonSet => Self::list_kind::<DaemonSet>(&client, context, namespace, kind, convert_workload, &events, &token).await,
+                Kind::ReplicaSet => Self::list_kind::<ReplicaSet>(&client, context, namespace, kind, convert_workload, &events, &token).await,
+                Kind::Job => Self::list_kind::<Job>(&client, context, namespace, kind, convert_workload, &events, &token).await,
+                Kind::Pod => Self::list_kind::<Pod>(&client, context, namespace, kind, convert_workload, &events, &token).await,
+                Kind::Service => Self::list_kind::<Service>(&client, context, namespace, kind, Self::from_service, &events, &token).await,
+                Kind::Deployment => continue,
+            };
+            if let Err(e) = listed {
+                error!("listing {}s: {}", kind, e);
+            }
         }
 
-        Ok(deployments)
+        Ok(())
     } 
 
-    pub async fn find_deployment(name_space: &str,name: String) -> Result<Option<PFDeployment>> {
-        let client = kube::Client::try_default().await?;
-        let api: kube::Api<Deployment> = kube::Api::namespaced(client.clone(), name_space);
-        let deployment = api.get(name.as_str()).await;
-        if let std::result::Result::Ok(d) = deployment {
-            let name = d.metadata.name.unwrap();
-            let namespace = d.metadata.namespace.unwrap();
-            let selector = d.spec.unwrap().selector;
-            return Ok(Some(PFDeployment { 
-                name,
-                namespace,
-                selector,
-                client: client.clone()
-            }));
-        }
-
-        Ok(None)
+    async fn list_kind<K>(client: &kube::Client,context: Option<&str>,namespace: Option<&str>,kind: Kind,convert: Convert<K>,events: &mpsc::Sender<WorkloadEvent>,token: &CancellationToken) -> Result<()>
+    where
+        K: kube::Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + Debug + DeserializeOwned + Send + Sync + 'static,
+    {
+        let mut listing = Listing::new(client.clone(), kind, convert, events.clone());
+        // pages of a cache listed now are sent as they arrive
+        let (items,mut first) = mpsc::channel(PAGE_SIZE as usize);
+        let listed = async {
+            let (cache,sent) = tokio::join!(
+                Cache::<K>::listing(context, namespace.unwrap_or_default(), Some(items)),
+                async {
+                    while let Some(item) = first.recv().await {
+                        listing.listed(item).await?;
+                    }
+                    Ok::<_,PFError>(())
+                },
+            );
+            sent?;
+            let cache = cache?;
+            listing.synced(cache.store.state()).await?;
+            Ok::<_,PFError>(cache)
+        };
+        let cache = tokio::select! {
+            cache = listed => cache?,
+            _ = token.cancelled() => return Ok(()),
+        };
+        let token = token.clone();
+        tokio::spawn(async move {
+            loop {
+                // created before comparing, so no change is missed
+                let changed = cache.changed.notified();
+                if listing.changed(cache.store.state()).await.is_err() {
+                    return;
+                }
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = changed => {}
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Looks up a target by its reference, see `Kind::parse`, in the cache
+    /// of its kind.
+    pub async fn find_deployment(context: Option<&str>,name_space: &str,reference: String) -> Result<Option<PFDeployment>> {
+        let (kind,name) = Kind::parse(reference.as_str());
+        match kind {
+            Kind::Deployment => Self::cached::<Deployment>(context, name_space, name, Self::from_workload).await,
+            Kind::StatefulSet => Self::cached::<StatefulSet>(context, name_space, name, Self::from_workload).await,
+            Kind::DaemonSet => Self::cached::<DaemonSet>(context, name_space, name, Self::from_workload).await,
+            Kind::ReplicaSet => Self::cached::<ReplicaSet>(context, name_space, name, Self::from_workload).await,
+            Kind::Job => Self::cached::<Job>(context, name_space, name, Self::from_workload).await,
+            Kind::Pod => Self::cached::<Pod>(context, name_space, name, Self::from_workload).await,
+            Kind::Service => Self::cached::<Service>(context, name_space, name, Self::from_service).await,
+        }
     } 
 
-    pub async fn find_pod(&self) -> Result<Option<PFPod>> {
-        let api: kube::Api<Pod> = kube::Api::namespaced(self.client.clone(), self.namespace.as_str());
-        let mut label_selector = "".into();
-        if let Some(selector) = self.selector.clone().match_labels {
-            label_selector = selector.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(",");
-        }
-
-        let list_options = ListParams::default().labels(label_selector.as_str());
-        let list = api.list(&list_options).await?;
-        if list.items.len() == 0 {
-            return Ok(None);
-        }
-
-        for pod in list.items {
-            let name = pod.metadata.name.unwrap();
-            let namespace = pod.metadata.namespace.unwrap();
-            let port = if let Some(spec) = pod.spec {
-                if let Some(ports) = spec.containers[0].ports.as_ref() {
-                    ports[0].container_port
-                } else { 0 }
-            } else { 0 };
-            return Ok(Some(PFPod {
-                name,
-                namespace,
-                port: port as u16,
-                forward: port as u16,
-                client: self.client.clone(),
-            }));
-        }
-        
-
-        Ok(None)
+    async fn cached<K>(context: Option<&str>,namespace: &str,name: &str,convert: Convert<K>) -> Result<Option<PFDeployment>>
+    where
+        K: kube::Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + Debug + DeserializeOwned + Send + Sync + 'static,
+    {
+        let cache = Cache::<K>::get(context, namespace).await?;
+        let client = client(context).await?;
+        Ok(cache.named(namespace, name).and_then(|item| convert((*item).clone(), client)))
+    }
+
+    fn from_workload<K: Workload>(workload: K,client: kube::Client) -> Option<PFDeployment> {
+        let selector = match K::KIND {
+            Kind::Pod => LabelSelector::default(),
+            _ => workload.selector()?,
+        };
+        let ports = workload.ports();
+        let metadata = workload.meta();
+        Some(PFDeployment { 
+            name: metadata.name.clone()?,
+            namespace: metadata.namespace.clone()?,
+            kind: K::KIND,
+            ports,
+            selector,
+            service_ports: Vec::new(),
+            client,
+        })
+    }
+
+    /// Services without a selector or of type ExternalName have no pods to
+    /// forward to.
+    fn from_service(service: Service,client: kube::Client) -> Option<PFDeployment> {
+        let spec = service.spec?;
+        if spec.type_.as_deref() == Some("ExternalName") {
+            return None;
+        }
+        let selector = spec.selector.filter(|selector| !selector.is_empty())?;
+        let service_ports = spec.ports.unwrap_or_default();
+        Some(PFDeployment {
+            name: service.metadata.name?,
+            namespace: service.metadata.namespace?,
+            kind: Kind::Service,
+            ports: service_ports.iter().map(|port| ContainerPort {
+                container: "".into(),
+                name: port.name.clone(),
+                port: port.port as u16,
+            }).collect(),
+            selector: LabelSelector { match_labels: Some(selector), ..Default::default() },
+            service_ports,
+            client,
+        })
+    }
+
+    /// How the target is referred to in the list and in the config: the
+    /// bare name for deployments, `kind/name` otherwise.
+    pub fn reference(&self) -> String {
+        reference(self.kind, self.name.as_str())
+    }
+
+    /// The selector of the pods of a workload or service, refused by
+    /// `label_selector` when it would select too broadly.
+    fn pod_selector(&self) -> Result<Selector> {
+        label_selector(&self.selector)
+    }
+
+    /// The caches the pods of the target are resolved from.
+    async fn pod_caches(&self,context: Option<&str>) -> Result<PodCaches> {
+        let namespace = self.namespace.as_str();
+        match self.kind {
+            Kind::Service => futures::try_join!(Cache::<Pod>::get(context, namespace), Cache::<EndpointSlice>::get(context, namespace))
+                .map(|(pods,slices)| (pods,Some(slices))),
+            _ => Cache::<Pod>::get(context, namespace).await.map(|pods| (pods,None)),
+        }
+    }
+
+    /// Lists the pods the forward may connect to.
+    pub async fn find_pods(&self,spec: &ForwardSpec) -> Result<Vec<PFPod>> {
+        match self.kind {
+            Kind::Service => self.endpoint_pods(spec).await,
+            _ => self.selected_pods(spec).await,
+        }
+    }
+
+    /// The service port a forward connects to: the one matching the remote
+    /// port by number or name, the first one without a remote port.
+    fn service_port(&self,remote: Option<&RemotePort>) -> Option<&ServicePort> {
+        self.service_ports.iter().find(|port| match remote {
+            Some(RemotePort::Number(number)) => port.port == *number as i32,
+            Some(RemotePort::Name(name)) => port.name.as_ref() == Some(name),
+            None => true,
+        })
+    }
+
+    /// Lists the ready pods behind the service from the cache of its
+    /// EndpointSlices. The slices carry the target port resolved for their
+    /// pods, named target ports included; the service spec is only used
+    /// when they do not. The pods themselves are read from the pod cache,
+    /// for their creation time and for named ports the slices leave
+    /// unresolved.
+    async fn endpoint_pods(&self,spec: &ForwardSpec) -> Result<Vec<PFPod>> {
+        let Some(service_port) = self.service_port(spec.remote.as_ref()) else {
+            return Err(PFError::ResourceNotFound(format!("Port {}", spec.remote.as_ref().map(|r| r.to_string()).unwrap_or_default())));
+        };
+        let context = spec.context.as_deref();
+        let (slices,selected) = futures::try_join!(
+            Cache::<EndpointSlice>::get(context, self.namespace.as_str()),
+            Cache::<Pod>::get(context, self.namespace.as_str()),
+        )?;
+        let slices = slices.store.state().into_iter()
+            .filter(|slice| slice.metadata.labels.as_ref().and_then(|labels| labels.get(SERVICE_NAME_LABEL)) == Some(&self.name));
+
+        let mut pods: Vec<PFPod> = Vec::new();
+        let mut not_ready = 0;
+        for slice in slices {
+            let target = slice.ports.iter().flatten()
+                .find(|port| port.name.as_deref().unwrap_or_default() == service_port.name.as_deref().unwrap_or_default())
+                .and_then(|port| port.port)
+                .map(|port| RemotePort::Number(port as u16))
+                .unwrap_or_else(|| match &service_port.target_port {
+                    Some(IntOrString::Int(port)) => RemotePort::Number(*port as u16),
+                    Some(IntOrString::String(name)) => RemotePort::Name(name.clone()),
+                    None => RemotePort::Number(service_port.port as u16),
+                });
+
+            for endpoint in slice.endpoints.iter() {
+                let ready = endpoint.conditions.as_ref().and_then(|conditions| conditions.ready).unwrap_or(true);
+                let Some(name) = endpoint.target_ref.as_ref()
+                    .filter(|target| target.kind.as_deref() == Some("Pod"))
+                    .and_then(|target| target.name.clone()) else {
+                    continue;
+                };
+                if !ready {
+                    not_ready += 1;
+                    continue;
+                }
+                if pods.iter().any(|pod| pod.name == name) {
+                    continue;
+                }
+                let pod = selected.named(self.namespace.as_str(), name.as_str());
+                let port = match &target {
+                    RemotePort::Number(port) => *port,
+                    RemotePort::Name(_) => {
+                        let pod_spec = ForwardSpec { remote: Some(target.clone()), ..Default::default() };
+                        let port = pod.as_ref()
+                            .and_then(|pod| pod.spec.as_ref())
+                            .and_then(|pod| resolve_port(&pod.containers, &pod_spec));
+                        let Some(port) = port else {
+                            continue;
+                        };
+                        port
+                    }
+                };
+                pods.push(PFPod {
+                    name,
+                    namespace: self.namespace.clone(),
+                    port,
+                    forward: spec.port,
+                    created: pod.and_then(|pod| pod.metadata.creation_timestamp.clone()),
+                    client: self.client.clone(),
+                });
+            }
+        }
+        if pods.is_empty() {
+            // pods still starting may not be in a slice yet, the service's
+            // pods tell why none is ready
+            let selector = self.pod_selector()?;
+            let starting: Vec<Arc<Pod>> = selected.store.state().into_iter()
+                .filter(|pod| pod.metadata.labels.as_ref().is_some_and(|labels| selector.matches(labels)))
+                .filter(|pod| not_ready_reason(pod).is_some())
+                .collect();
+            if !starting.is_empty() {
+                return Err(pods_not_ready(&starting));
+            }
+            if not_ready > 0 {
+                return Err(PFError::PodNotReady(format!("0/{} ready", not_ready)));
+            }
+        }
+        Ok(pods)
+    }
+
+    /// Whether a pod of the workload is the pinned StatefulSet ordinal or
+    /// runs on the pinned DaemonSet node.
+    fn pinned(&self,pod: &Pod,spec: &ForwardSpec) -> bool {
+        let Some(pin) = spec.pin.as_ref() else {
+            return true;
+        };
+        match self.kind {
+            Kind::StatefulSet => pod.metadata.name.as_ref() == Some(&format!("{}-{}", self.name, pin)),
+            Kind::DaemonSet => pod.spec.as_ref().and_then(|spec| spec.node_name.as_ref()) == Some(pin),
+            _ => true,
+        }
+    }
+
+    /// Lists the pods matching the workload's selector that have the port
+    /// the forward connects to, from the pod cache of the namespace.
+    async fn selected_pods(&self,spec: &ForwardSpec) -> Result<Vec<PFPod>> {
+        let selector = match self.kind {
+            Kind::Pod => None,
+            _ => Some(self.pod_selector()?),
+        };
+        let cache = Cache::<Pod>::get(spec.context.as_deref(), self.namespace.as_str()).await?;
+        let (ready,not_ready): (Vec<Arc<Pod>>,Vec<Arc<Pod>>) = cache.store.state().into_iter()
+            .filter(|pod| match &selector {
+                Some(selector) => pod.metadata.labels.as_ref().is_some_and(|labels| selector.matches(labels)),
+                None => pod.metadata.name.as_ref() == Some(&self.name),
+            })
+            .filter(|pod| self.pinned(pod, spec))
+            .partition(|pod| not_ready_reason(pod).is_none());
+        if ready.is_empty() && !not_ready.is_empty() {
+            return Err(pods_not_ready(&not_ready));
+        }
+        Ok(ready.iter()
+            .filter_map(|pod| PFPod::new(pod, self.client.clone(), spec))
+            .collect())
     } 
 
-    pub async fn port_forward(namespace:String,name:String,port:u16) -> Result<()> {
-        let deployment = Self::find_deployment(namespace.as_str(), name.clone()).await?; 
+    pub async fn port_forward(namespace:String,name:String,spec:ForwardSpec,token: CancellationToken,reporter: Reporter) -> Result<()> {
+        reporter.report(ForwardState::Resolving);
+        match missing_permissions(spec.context.clone(), namespace.clone(), Permission::forward(Kind::parse(name.as_str()).0)).await {
+            Ok(missing) if !missing.is_empty() => {
+                let missing: Vec<String> = missing.iter().map(|permission| permission.to_string()).collect();
+                return Err(PFError::Unauthorized(missing.join(", ")));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("checking permissions: {}", e),
+        }
+        let deployment = Self::find_deployment(spec.context.as_deref(), namespace.as_str(), name.clone()).await?; 
         if let Some(deployment) = deployment {
-            let pod = deployment.find_pod().await?;
-            if let Some(pod) = pod {
-                pod.port_forward(port).await?;
-                return Ok(());
+            let pods = deployment.find_pods(&spec).await?;
+            if !pods.is_empty() {
+                let upstream = Upstream::new(deployment,spec.clone(),token.clone(),reporter);
+                upstream.set(&mut *upstream.backends.lock().await, pods);
+                // held while the forward runs, failover resolves from them too
+                let caches = upstream.deployment.pod_caches(spec.context.as_deref()).await?;
+                // the pod set follows new replicas only while watched
+                if spec.watch || upstream.deployment.kind.spreads(spec.pin.as_deref()) {
+                    let upstream = upstream.clone();
+                    let caches = caches.clone();
+                    tokio::spawn(async move {
+                        tokio::select! {
+                            _ = upstream.token.cancelled() => {}
+                            _ = upstream.watch(caches) => {}
+                        }
+                    });
+                }
+                let result = upstream.serve(spec.port).await;
+                drop(caches);
+                token.cancel();
+                return result;
             } 
-            return Err(Box::new(PFError::ResourceNotFound("Pod".into())));
+            return Err(PFError::ResourceNotFound("Pod".into()));
              
         }
-        return Err(Box::new(PFError::ResourceNotFound("Deployment".into())));
+        Err(PFError::ResourceNotFound(Kind::parse(name.as_str()).0.to_string()))
     }
 }
 
- 
\ No newline at end of file
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelectorRequirement;
+    use super::*;
+
+    fn expression(key: &str,operator: &str,values: Option<&[&str]>) -> LabelSelectorRequirement {
+        LabelSelectorRequirement {
+            key: key.into(),
+            operator: operator.into(),
+            values: values.map(|values| values.iter().map(|value| value.to_string()).collect()),
+        }
+    }
+
+    fn selector(labels: &[(&str,&str)],expressions: Vec<LabelSelectorRequirement>) -> LabelSelector {
+        LabelSelector {
+            match_labels: Some(labels.iter().map(|(key,value)| (key.to_string(),value.to_string())).collect::<BTreeMap<_,_>>()).filter(|labels| !labels.is_empty()),
+            match_expressions: Some(expressions).filter(|expressions| !expressions.is_empty()),
+        }
+    }
+
+    fn invalid(selector: &LabelSelector) -> String {
+        match label_selector(selector) {
+            Err(PFError::InvalidSelector(reason)) => reason,
+            other => panic!("expected an invalid selector, got {:?}", other),
+        }
+    }
+
+    /// Whether the selector picks a pod with these labels.
+    fn picks(selector: &LabelSelector,labels: &[(&str,&str)]) -> bool {
+        let labels: BTreeMap<String,String> = labels.iter().map(|(key,value)| (key.to_string(),value.to_string())).collect();
+        label_selector(selector).unwrap().matches(&labels)
+    }
+
+    #[test]
+    fn match_labels() {
+        let selector = selector(&[("app","web"),("tier","front")], vec![]);
+        assert!(picks(&selector, &[("app","web"),("tier","front"),("pod-template-hash","abc")]));
+        assert!(!picks(&selector, &[("app","web")]));
+        assert!(!picks(&selector, &[("app","api"),("tier","front")]));
+    }
+
+    #[test]
+    fn operator_in() {
+        let selector = selector(&[], vec![expression("env", "In", Some(&["dev","staging"]))]);
+        assert!(picks(&selector, &[("env","dev")]));
+        assert!(picks(&selector, &[("env","staging")]));
+        assert!(!picks(&selector, &[("env","prod")]));
+        assert!(!picks(&selector, &[]));
+    }
+
+    #[test]
+    fn operator_not_in() {
+        let selector = selector(&[], vec![expression("env", "NotIn", Some(&["prod"]))]);
+        assert!(picks(&selector, &[("env","dev")]));
+        assert!(picks(&selector, &[]));
+        assert!(!picks(&selector, &[("env","prod")]));
+    }
+
+    #[test]
+    fn operator_exists() {
+        let selector = selector(&[], vec![expression("canary", "Exists", None)]);
+        assert!(picks(&selector, &[("canary","")]));
+        assert!(picks(&selector, &[("canary","yes")]));
+        assert!(!picks(&selector, &[("app","web")]));
+    }
+
+    #[test]
+    fn operator_does_not_exist() {
+        let selector = selector(&[], vec![expression("canary", "DoesNotExist", Some(&[]))]);
+        assert!(picks(&selector, &[("app","web")]));
+        assert!(!picks(&selector, &[("canary","yes")]));
+    }
+
+    #[test]
+    fn labels_and_expressions() {
+        let selector = selector(&[("app","web")], vec![
+            expression("env", "In", Some(&["dev"])),
+            expression("canary", "DoesNotExist", None),
+        ]);
+        assert!(picks(&selector, &[("app","web"),("env","dev")]));
+        assert!(!picks(&selector, &[("app","web"),("env","dev"),("canary","yes")]));
+        assert!(!picks(&selector, &[("app","web"),("env","prod")]));
+        assert!(!picks(&selector, &[("env","dev")]));
+    }
+    #[test]
+    fn set_operators_need_values() {
+        for operator in ["In", "NotIn"] {
+            let missing = selector(&[], vec![expression("env", operator, None)]);
+            assert_eq!(invalid(&missing), format!("env {} without values", operator));
+            let empty = selector(&[], vec![expression("env", operator, Some(&[]))]);
+            assert_eq!(invalid(&empty), format!("env {} without values", operator));
+        }
+    }
+
+    #[test]
+    fn existence_operators_take_no_values() {
+        for operator in ["Exists", "DoesNotExist"] {
+            let selector = selector(&[], vec![expression("canary", operator, Some(&["yes"]))]);
+            assert_eq!(invalid(&selector), format!("canary {} with values", operator));
+        }
+    }
+
+    #[test]
+    fn unknown_operator() {
+        let selector = selector(&[], vec![expression("env", "Gt", Some(&["1"]))]);
+        assert_eq!(invalid(&selector), "unknown operator Gt on env");
+    }
+
+    #[test]
+    fn empty_selector() {
+        assert_eq!(invalid(&LabelSelector::default()), "empty selector");
+        assert_eq!(invalid(&selector(&[], vec![])), "empty selector");
+    }
+}
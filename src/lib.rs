@@ -6,14 +6,16 @@ mod widget;
 mod message;
 mod error;
 mod util;
+mod forward;
 
-pub use k8s::PFDeployment;
+pub use k8s::{ContainerPort, Kind, PFDeployment, Permission};
 pub use app::App;
 pub use widget::*;
 pub use message::Message;
 pub use error::PFError;
+pub use forward::{Balance, Bind, ForwardSpec, ForwardState, RemotePort, SessionPool};
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+type Result<T> = std::result::Result<T, PFError>;
 
 pub type Renderer = iced::Renderer;
 pub type Theme = theme::Theme;
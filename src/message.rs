@@ -2,23 +2,60 @@ use std::path::PathBuf;
 
 use iced::window;
 
-use crate::PFDeployment;
+use crate::forward::ForwardKey;
+use crate::k8s::WorkloadEvent;
+use crate::{Balance, ContainerPort, ForwardState, PFDeployment, PFError, Permission};
 
 #[derive(Debug,Clone)]
 pub enum Message {
     FilterDeployment(window::Id,String),
     ListDeployment(window::Id,Vec<PFDeployment>),
+    WorkloadChanged(window::Id,WorkloadEvent),
+    SelectContext(window::Id,String),
     SelectNamespace(window::Id,String),
-    Choose(window::Id,String),
+    PickNamespace(window::Id,String),
+    FavouriteNamespace(window::Id),
+    ListNamespace(window::Id,Result<Vec<String>,PFError>),
+    ReloadNamespaces(window::Id),
+    Permissions{id:window::Id,namespace:String,missing:Vec<Permission>},
+    Choose{id:window::Id,namespace:String,name:String},
     NewWindow,
+    /// The window was closed.
+    WindowClosed(window::Id),
     Ignore,
     Load(window::Id),
-    Forward{id:window::Id,name:String,port:u16},
+    Forward{id:window::Id,name:String},
+    StopForward{id:window::Id,name:String},
+    RestartForward{id:window::Id,name:String},
+    ForwardStatus{id:window::Id,key:ForwardKey,handle:u64,state:ForwardState},
     SaveConfig(Option<(window::Id,PathBuf)>),
+    SavedConfig(window::Id,Result<PathBuf,PFError>),
+    /// The main window was autosaved.
+    Autosaved(Result<(),PFError>),
+    RestartOnStartup(window::Id,bool),
+    SelectProfile(window::Id,String),
+    InputProfile(window::Id,String),
+    CreateProfile(window::Id),
+    DeleteProfile(window::Id),
+    StartProfile(window::Id),
+    StopProfile(window::Id),
+    /// Adds the deployment to the selected profile, or removes it.
+    ToggleProfileTarget{id:window::Id,name:String},
     LoadConfig(Option<(window::Id,PathBuf)>),
     InputForward{id: window::Id,port:String},
+    InputRemote{id: window::Id,remote:String},
+    InputBind{id: window::Id,bind:String},
+    InputPin{id: window::Id,pin:String},
+    ChoosePort{id: window::Id,port:ContainerPort},
+    AddMapping(window::Id),
+    RemoveMapping{id: window::Id,local:u16},
+    InputWatch{id: window::Id,watch:bool},
+    InputPool{id: window::Id,pool:String},
+    InputBalance{id: window::Id,balance:Balance},
     Forwarded(window::Id,bool),
-    Error(window::Id,String,u8),
+    AllNamespaces(window::Id,bool),
+    /// Listing the namespace failed.
+    Error(window::Id,PFError),
     SaveConfigDialog(window::Id),
     LoadConfigDialog(window::Id)
 }
\ No newline at end of file
@@ -2,6 +2,8 @@ use iced::application;
 use iced::color;
 use iced::widget::checkbox;
 use iced::widget::scrollable;
+use iced::widget::pick_list;
+use iced::overlay::menu;
 use iced::widget::{button,text_input,container,text};
 use iced::Background;
 use iced::Border;
@@ -89,6 +91,12 @@ pub enum Button {
     Start,
     Entry,
     Search,
+    Pending,
+    Warning,
+    Failed,
+    Denied,
+    /// A denied entry that is selected, greyed out but outlined.
+    DeniedSelected,
 }
 
 impl button::StyleSheet for Theme {
@@ -116,6 +124,19 @@ impl button::StyleSheet for Theme {
                 ..appearance
             },
             Button::Search => active_appearance(None,palette.peace),
+            Button::Pending => active_appearance(None,palette.peace),
+            Button::Warning => active_appearance(None,palette.warning),
+            Button::Failed => active_appearance(None,palette.error),
+            Button::Denied => button::Appearance {
+                background: Some(Background::Color(palette.foreground)),
+                text_color: Color { a: 0.4, ..palette.text },
+                border: border(palette.border),
+                ..appearance
+            },
+            Button::DeniedSelected => button::Appearance {
+                border: border(Color { a: 0.5, ..palette.accent }),
+                ..self.active(&Button::Denied)
+            },
         }
     }
 
@@ -137,6 +158,13 @@ impl button::StyleSheet for Theme {
                 ..hover_appearance(palette.accent, Some(palette.text))
             },
             Button::Search => hover_appearance(palette.peace,Some(palette.text)),
+            Button::Pending => hover_appearance(palette.peace,Some(palette.text)),
+            Button::Warning => hover_appearance(palette.warning,Some(palette.text)),
+            Button::Failed => hover_appearance(palette.error,Some(palette.text)),
+            Button::Denied | Button::DeniedSelected => button::Appearance {
+                border: border(Color { a: 0.5, ..palette.accent }),
+                ..active
+            },
         }
     }
 
@@ -194,19 +222,19 @@ impl text_input::StyleSheet for Theme {
         }
     }
     
-    fn placeholder_color(&self, style: &Self::Style) -> Color {
+    fn placeholder_color(&self, _style: &Self::Style) -> Color {
         color!(0xFF, 0xFF, 0xFF,0.3)
     }
     
-    fn value_color(&self, style: &Self::Style) -> Color {
+    fn value_color(&self, _style: &Self::Style) -> Color {
        self.palette().accent
     }
     
-    fn disabled_color(&self, style: &Self::Style) -> Color {
+    fn disabled_color(&self, _style: &Self::Style) -> Color {
         self.palette().text
     }
     
-    fn selection_color(&self, style: &Self::Style) -> Color {
+    fn selection_color(&self, _style: &Self::Style) -> Color {
         Color {
             a: 0.5,
             ..self.palette().accent
@@ -470,3 +498,48 @@ impl checkbox::StyleSheet for Theme {
 }
 
 
+
+#[derive(Default, Debug, Clone, Copy)]
+pub enum PickList {
+    #[default]
+    Normal,
+}
+
+impl pick_list::StyleSheet for Theme {
+    type Style = PickList;
+
+    fn active(&self, _style: &Self::Style) -> pick_list::Appearance {
+        let p = self.palette();
+
+        pick_list::Appearance {
+            text_color: p.accent,
+            placeholder_color: color!(0xFF, 0xFF, 0xFF, 0.3),
+            handle_color: p.accent,
+            background: p.middleground.into(),
+            border: border(p.border),
+        }
+    }
+
+    fn hovered(&self, style: &Self::Style) -> pick_list::Appearance {
+        pick_list::Appearance {
+            border: border(self.palette().accent),
+            ..self.active(style)
+        }
+    }
+}
+
+impl menu::StyleSheet for Theme {
+    type Style = PickList;
+
+    fn appearance(&self, _style: &Self::Style) -> menu::Appearance {
+        let p = self.palette();
+
+        menu::Appearance {
+            text_color: p.text,
+            background: p.middleground.into(),
+            border: border(p.border),
+            selected_text_color: p.background,
+            selected_background: p.accent.into(),
+        }
+    }
+}
@@ -1,32 +1,99 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
+use futures::SinkExt;
 use iced::{window, Command};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
 
-use crate::{Message, PFDeployment};
+use crate::config::Format;
+use crate::forward::{ForwardHandle, ForwardKey, Reporter};
+use crate::k8s::{list_namespaces, missing_permissions, WorkloadEvent};
+use crate::{ForwardState, Message, PFDeployment, PFError, Permission};
 
-pub fn load_deployment(id:window::Id, namespace: String) -> Command<Message> {
-    let namespace = namespace.clone();
-    Command::perform(PFDeployment::list_deployment(namespace),move|v|{
-        
-        match v {
-            Ok(list) => Message::ListDeployment(id,list),
-            Err(e) => Message::Error(id,format!("{}",e),0),
+/// Lists a namespace, every namespace when `None`, streaming its targets
+/// page by page, then streams their changes until `token` is cancelled.
+pub fn load_deployment(id:window::Id, context: Option<String>, namespace: Option<String>, token: CancellationToken) -> Command<Message> {
+    iced::command::channel(64, move |mut sender| async move {
+        let (events,mut changes) = mpsc::channel(64);
+        let mut listing = tokio::spawn(PFDeployment::list_deployment(context,namespace,events,token.clone()));
+        let mut listed = false;
+        loop {
+            let message = tokio::select! {
+                result = &mut listing, if !listed => {
+                    listed = true;
+                    match result {
+                        Ok(Ok(())) => continue,
+                        Ok(Err(e)) => Message::Error(id,e),
+                        Err(e) => Message::Error(id,PFError::Connection(Arc::new(e))),
+                    }
+                }
+                event = changes.recv() => match event {
+                    Some(WorkloadEvent::Listed(page)) => Message::ListDeployment(id,page),
+                    Some(event) => Message::WorkloadChanged(id,event),
+                    None => return,
+                },
+            };
+            // events buffered before a new listing replaced this one
+            if token.is_cancelled() {
+                return;
+            }
+            if sender.send(message).await.is_err() {
+                return;
+            }
         }
     })
 }
 
-pub fn port_forward(id: window::Id,namespace:String,name: String,port: u16) -> Command<Message> {
-    Command::perform(PFDeployment::port_forward(namespace,name, port),move|v|{
-        match v {
-            Ok(_) => Message::Ignore,
-            Err(e) => Message::Error(id,format!("{}",e),1),
-        }
+pub fn load_namespace(id:window::Id, context: Option<String>) -> Command<Message> {
+    Command::perform(list_namespaces(context),move|v|{
+        Message::ListNamespace(id,v)
+    })
+}
+
+/// Checks what forwarding needs in a namespace. A failed check is logged and
+/// taken as allowed, forwarding then reports the error itself.
+pub fn check_permissions(id:window::Id, context: Option<String>, namespace: String) -> Command<Message> {
+    Command::perform(missing_permissions(context,namespace.clone(),&Permission::FORWARD),move|missing|{
+        let missing = missing.unwrap_or_else(|e| {
+            error!("checking permissions: {}",e);
+            vec![]
+        });
+        Message::Permissions{id,namespace,missing}
+    })
+}
+
+pub fn port_forward(id: window::Id,key: ForwardKey,handle: &ForwardHandle) -> Command<Message> {
+    let handle = handle.clone();
+    iced::command::channel(16, move |sender| async move {
+        let ((_,namespace,name),_) = key.clone();
+        let reporter = Reporter::new(id,key,handle.id,sender);
+        let result = PFDeployment::port_forward(namespace,name,handle.spec.clone(),handle.token(),reporter.clone()).await;
+        reporter.report(match result {
+            Ok(_) => ForwardState::Stopped,
+            Err(e) => ForwardState::Failed(e),
+        });
     })
 }
 
-pub async fn file_dialog(id: window::Id) -> (Option<(window::Id,PathBuf)>) {
+pub async fn open_dialog(id: window::Id) -> Option<(window::Id,PathBuf)> {
+    let extensions: Vec<&str> = Format::ALL.iter().flat_map(|format| format.extensions()).copied().collect();
     rfd::AsyncFileDialog::new()
+        .add_filter("config", &extensions)
         .pick_file()
         .await
         .map(|f| (id,f.path().to_owned()))
-}
\ No newline at end of file
+}
+
+/// Asks where to save the config. The extension of the name given picks
+/// the format, JSON when it has none of them.
+pub async fn save_dialog(id: window::Id) -> Option<(window::Id,PathBuf)> {
+    let dialog = Format::ALL.iter().fold(rfd::AsyncFileDialog::new(), |dialog,format| {
+        dialog.add_filter(format.to_string(), format.extensions())
+    });
+    dialog
+        .set_file_name("portforward.json")
+        .save_file()
+        .await
+        .map(|f| (id,f.path().to_owned()))
+}
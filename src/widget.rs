@@ -1,8 +1,9 @@
+use std::{fmt::Display, path::PathBuf};
 use iced::{
-    alignment::Horizontal, widget::{ button, column, container, row, scrollable, text, text_input, Column, Space}, window, Length
+    alignment::Horizontal, widget::{ button, checkbox, column, combo_box, pick_list, container, row, scrollable, text, text_input, Column, Space}, window, Length
 };
 use once_cell::sync::Lazy;
-use crate::{config::{DataConfig, DeploymentConfig}, theme, Container, Element, Message, Text};
+use crate::{config::{DataConfig, NamespaceHistory}, theme, Container, Balance, Bind, Element, Kind, ForwardState, Message, PFError, Permission, Text};
 // tools
 fn centerd_container<'a,Message>(
     content: impl Into<Element<'a,Message>>
@@ -22,21 +23,106 @@ fn text_adv<'a>(str: impl ToString) -> Text<'a> {
     text(str).shaping(text::Shaping::Advanced)
 }
 
+/// An error with what the user can do about it, and a retry button when
+/// trying again may help.
+pub fn error_view<'a>(error: &PFError,retry: Option<Message>) -> Element<'a,Message> {
+    column![text(error.to_string()).style(theme::Text::Error)]
+        .push_maybe(error.hint().map(|hint| text(hint).size(12)))
+        .push_maybe(retry.filter(|_| error.is_retryable()).map(|retry| button("retry").on_press(retry)))
+        .spacing(4)
+        .into()
+}
+
  // namespace view
- pub fn widget_namespace(id: window::Id,data_config:&DataConfig) -> Element<Message> {
+
+/// A config file offered by the recent files menu.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct RecentFile(pub PathBuf);
+
+impl Display for RecentFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+/// A namespace offered by the namespace picker.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct NamespaceOption {
+    pub name: String,
+    pub favourite: bool,
+}
+
+impl Display for NamespaceOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.favourite {
+            true => write!(f, "★ {}", self.name),
+            false => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Searchable namespaces of the window's context: favourites, then the
+/// recently used ones, then the ones listed from the cluster. When they
+/// cannot be listed the namespace can still be typed in.
+#[derive(Debug,Clone)]
+pub struct NamespacePicker {
+    state: combo_box::State<NamespaceOption>,
+    options: Vec<NamespaceOption>,
+    pub listed: Vec<String>,
+    pub error: Option<PFError>,
+}
+
+impl Default for NamespacePicker {
+    fn default() -> Self {
+        Self {
+            state: combo_box::State::new(Vec::new()),
+            options: Vec::new(),
+            listed: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl NamespacePicker {
+    pub fn refresh(&mut self,history: &NamespaceHistory,current: &str) {
+        let mut options: Vec<NamespaceOption> = Vec::new();
+        for name in history.favourites.iter().chain(history.recent.iter()).chain(self.listed.iter()) {
+            if options.iter().all(|option| &option.name != name) {
+                options.push(NamespaceOption { name: name.clone(), favourite: history.is_favourite(name) });
+            }
+        }
+        let selection = options.iter().find(|option| option.name == current).cloned();
+        self.state = combo_box::State::with_selection(options.clone(), selection.as_ref());
+        self.options = options;
+    }
+}
+
+ pub fn widget_namespace<'a>(id: window::Id,data_config:&'a DataConfig,picker: &'a NamespacePicker) -> Element<'a, Message> {
     let title = "Load Data";
     let namespace = data_config.current_namespace.as_str();
-    let input = text_input("input namespace",namespace)
+    let selection = picker.options.iter().find(|option| option.name == namespace);
+    let input = combo_box(&picker.state,"input namespace",selection,move |v| Message::PickNamespace(id,v.name))
         .on_input(move|v| Message::SelectNamespace(id,v.clone()))
-        .style(theme::TextInputStyle::Inverted);
+        .text_input_style(theme::TextInputStyle::Inverted);
+    let favourite = button(if selection.is_some_and(|option| option.favourite) { "★" } else { "☆" })
+        .on_press_maybe((!namespace.is_empty()).then_some(Message::FavouriteNamespace(id)))
+        .style(theme::Button::Search);
+    let context = pick_list(data_config.contexts.clone(),data_config.current_context.clone(),move |v| Message::SelectContext(id,v))
+        .placeholder("context")
+        .width(Length::FillPortion(1));
     let button = button("Load Data")
         .on_press(Message::Load(id))
         .style(theme::Button::Primary);
 
         let content = column![
-            input,
+            row![context,input.width(Length::FillPortion(2)),favourite].spacing(8),
             button,
-        ].spacing(8);
+        ]
+        .push_maybe(picker.error.as_ref().map(|error| column![
+            text("namespaces can't be listed").size(12),
+            error_view(error,Some(Message::ReloadNamespaces(id))),
+        ]))
+        .spacing(8);
 
         let content = centerd_container(content
             .align_items(iced::Alignment::Start)
@@ -60,10 +146,58 @@ fn text_adv<'a>(str: impl ToString) -> Text<'a> {
     .into()
  }
 
+ /// Picks, creates and deletes profiles, and starts or stops the picked one.
+ /// `profile` is the number of targets of the picked profile and how many
+ /// of them are forwarding.
+ pub fn widget_profile(id: window::Id,data_config:&DataConfig,profiles: Vec<String>,profile: Option<(usize,usize)>) -> Element<'_, Message> {
+    let title = "Profiles";
+    let picked = pick_list(profiles,data_config.current_profile.clone(),move |v| Message::SelectProfile(id,v))
+        .placeholder("profile")
+        .width(Length::FillPortion(1));
+    let input = text_input("new profile",data_config.profile_input.as_str())
+        .on_input(move |v| Message::InputProfile(id,v))
+        .on_submit(Message::CreateProfile(id))
+        .style(theme::TextInputStyle::Inverted)
+        .width(Length::FillPortion(1));
+    let summary = match profile {
+        Some((targets,forwarding)) => text(format!("{} targets, {} forwarding",targets,forwarding)),
+        None => text("None Selected"),
+    };
+    let picked_message = |message: Message| profile.map(|_| message);
+
+    let content = column![
+        row![picked,input,button("create").on_press(Message::CreateProfile(id))].spacing(8),
+        row![
+            summary,
+            Space::with_width(Length::Fill),
+            button("start").on_press_maybe(picked_message(Message::StartProfile(id))),
+            button("stop").on_press_maybe(picked_message(Message::StopProfile(id))),
+            button("delete").on_press_maybe(picked_message(Message::DeleteProfile(id))),
+        ].spacing(8).align_items(iced::Alignment::Center),
+    ]
+    .push_maybe((!data_config.profile_skipped.is_empty()).then(|| {
+        text(format!("not started, no mappings saved: {}",data_config.profile_skipped.join(", "))).style(theme::Text::Warning)
+    }))
+    .spacing(8)
+    .padding(8);
+
+    container(
+        Column::new().spacing(10)
+        .push(title)
+        .push(
+            container(content)
+            .padding(8)
+            .style(theme::Container::Frame)
+            .width(Length::Fill)
+        )
+    )
+    .width(Length::Fill)
+    .into()
+ }
 
 // search bar
 pub static SEARCH_BAR_ID: Lazy<text_input::Id> = Lazy::new(text_input::Id::unique);
-pub fn widget_search_bar(id:window::Id,data_config:&DataConfig) -> Element<Message> {
+pub fn widget_search_bar(id:window::Id,data_config:&DataConfig) -> Element<'_, Message> {
     let search_value = data_config.search_value.as_str();
     let input = text_input("input deployment name",search_value)
         .id(SEARCH_BAR_ID.clone())
@@ -82,29 +216,44 @@ pub fn widget_search_bar(id:window::Id,data_config:&DataConfig) -> Element<Messa
 
 // entry list
 
-fn widget_view_entry((id,_index,entry):(window::Id,usize,&Entry)) ->Element<Message> {
+fn widget_view_entry((id,all_namespaces,entry):(window::Id,bool,&Entry)) ->Element<'_, Message> {
     // let check = checkbox("", entry.selected)
     //     .on_toggle(move |selected| Message::SelectDeployment {name:entry.name.clone(),selected})
     //     .style(theme::CheckBox::Entry);
-    let name_text = text_adv(entry.name.clone());
+    let (kind,name) = Kind::parse(entry.name.as_str());
+    let kind_text = text(kind.to_string()).size(12).width(80);
+    let name_text = text_adv(name);
 
-    let view = row![name_text]
+    let view = row![kind_text,name_text]
+        .push_maybe(all_namespaces.then(|| text(entry.namespace.clone()).size(12)))
         .spacing(4)
         .padding(1)
         .align_items(iced::Alignment::Center);
     button(view)
         .width(Length::Fill)
-        .on_press(Message::Choose(id,entry.name.clone()))
+        .on_press(Message::Choose{id,namespace:entry.namespace.clone(),name:entry.name.clone()})
         .padding(4)
-        .style(if entry.succeed {theme::Button::Start} else if entry.selected {theme::Button::Primary} else {theme::Button::Entry})
+        .style(match entry.state {
+            ForwardState::Listening => theme::Button::Start,
+            ForwardState::Resolving | ForwardState::Binding | ForwardState::Reconnecting(_) => theme::Button::Pending,
+            ForwardState::Degraded(_) => theme::Button::Warning,
+            ForwardState::Failed(_) => theme::Button::Failed,
+            ForwardState::Stopped if entry.denied && entry.selected => theme::Button::DeniedSelected,
+            ForwardState::Stopped if entry.denied => theme::Button::Denied,
+            ForwardState::Stopped if entry.selected => theme::Button::Primary,
+            ForwardState::Stopped => theme::Button::Entry,
+        })
         .into()
 }
 
 #[derive(Debug,Clone)]
 pub struct Entry {
+    pub namespace: String,
     pub name: String,
     pub selected: bool,
-    pub succeed: bool,
+    pub state: ForwardState,
+    /// The current identity lacks what forwarding to it needs.
+    pub denied: bool,
 }
 
 #[derive(Debug,Default,Clone)]
@@ -113,14 +262,11 @@ pub struct EntryList {
 }
 
 impl EntryList {
-    pub fn view(&self,id: window::Id,error: String) ->Element<Message> {
+    pub fn view(&self,id: window::Id,error: Option<&PFError>,all_namespaces: bool) ->Element<'_, Message> {
         let entries = &self.entries;
-        if !error.is_empty() {
-            return centerd_container(
-                column![]
-                .push(centered_text(error.clone()))
-                .align_items(iced::Alignment::Center)
-            ).style(theme::Container::BlackHovered(false)).into();
+        if let Some(error) = error {
+            return centerd_container(error_view(error,Some(Message::Load(id))))
+                .style(theme::Container::BlackHovered(false)).into();
         }
         if entries.is_empty() {
             return centerd_container(
@@ -131,7 +277,7 @@ impl EntryList {
         }
 
         centerd_container(scrollable(row![
-            column(entries.iter().enumerate().map(move |v|{widget_view_entry((id,v.0,v.1))}))
+            column(entries.iter().map(move |entry|{widget_view_entry((id,all_namespaces,entry))}))
             .spacing(10)
             .padding(5),
             Space::with_width(15)
@@ -148,33 +294,121 @@ pub enum ForwardBox {
     #[default]
     None,
     Selected,
-    Error(String)
 }
 
 impl ForwardBox {
-    pub fn view(&self,id: window::Id,data_config:&DataConfig) -> Element<Message> {
+    pub fn view(&self,id: window::Id,data_config:&DataConfig,states: Vec<ForwardState>,running: bool,missing: Vec<Permission>,profile: Option<bool>) -> Element<'_, Message> {
         let title = "Forward";
 
         let content = match &self {
-            ForwardBox::Error(v) => column![text(v.clone())],
             ForwardBox::None => column![text("None Selected")],
             ForwardBox::Selected => {
                 let port = data_config.current_port.clone();
                 let name = data_config.current_deployment.clone();
-                let forward_input = text_input("forward port",port.clone().as_str())
+                let forward_input = text_input("local port",port.as_str())
                 .on_input(move |v| Message::InputForward{id,port:v.clone()})
+                .on_submit(Message::AddMapping(id))
                 .style(theme::TextInputStyle::Inverted);
 
-                let button = button("forward").on_press(Message::Forward{
-                    id,
-                    name:name.clone(),
-                    port:port.parse::<u16>().unwrap_or(0),
+                let bind = data_config.current_bind.clone();
+                let bind_input = text_input("127.0.0.1",bind.as_str())
+                .on_input(move |v| Message::InputBind{id,bind:v.clone()})
+                .on_submit(Message::AddMapping(id))
+                .style(theme::TextInputStyle::Inverted);
+                let bind_hint = match Bind::parse(bind.as_str()) {
+                    None => text("bind to 127.0.0.1, ::1, dual-stack, 0.0.0.0 or an interface address"),
+                    Some(bind) if bind.is_exposed() => text(format!("{} is reachable from other machines", bind)).style(theme::Text::Warning),
+                    Some(_) => text(""),
+                };
+
+                let pin = Kind::parse(name.as_str()).0.pin().map(|placeholder| {
+                    text_input(placeholder,data_config.current_pin.as_str())
+                        .on_input(move |v| Message::InputPin{id,pin:v.clone()})
+                        .style(theme::TextInputStyle::Inverted)
                 });
 
+                let pool = data_config.current_pool.clone();
+                let pool_input = text_input("pool size",pool.as_str())
+                .on_input(move |v| Message::InputPool{id,pool:v.clone()})
+                .style(theme::TextInputStyle::Inverted);
+
+                let remote = data_config.current_remote.clone();
+                let remote_input = text_input("remote port",remote.as_str())
+                .on_input(move |v| Message::InputRemote{id,remote:v.clone()})
+                .on_submit(Message::AddMapping(id))
+                .style(theme::TextInputStyle::Inverted);
+                let chosen = data_config.current_ports.iter().find(|port| {
+                    data_config.current_container.as_deref().unwrap_or_default() == port.container && port.remote().to_string() == remote
+                }).cloned();
+                let ports = pick_list(data_config.current_ports.clone(),chosen,move |port| Message::ChoosePort{id,port})
+                    .placeholder("container port")
+                    .width(Length::Fill);
+
+                let mappings = column(data_config.current_mappings.iter().zip(states).map(|(mapping,state)| {
+                    let local = mapping.local;
+                    let mapping_text = match mapping.bind.is_exposed() {
+                        true => text(format!("{} (exposed)", mapping)).style(theme::Text::Warning),
+                        false => text(mapping.to_string()),
+                    };
+                    let (state_text,failed) = match &state {
+                        ForwardState::Failed(e) => (text("Failed"),Some(error_view(e,Some(Message::Forward{id,name:name.clone()})))),
+                        state => (text(state.to_string()),None),
+                    };
+                    column![
+                        row![
+                            mapping_text,
+                            Space::with_width(Length::Fill),
+                            state_text,
+                            button("remove").on_press(Message::RemoveMapping{id,local}),
+                        ]
+                        .spacing(8)
+                        .align_items(iced::Alignment::Center),
+                    ]
+                    .push_maybe(failed)
+                    .spacing(4)
+                    .into()
+                })).spacing(5);
+
+                let denied = match missing.is_empty() {
+                    true => None,
+                    false => {
+                        let missing: Vec<String> = missing.iter().map(|permission| permission.to_string()).collect();
+                        Some(text(format!("missing permission: {}", missing.join(", "))).style(theme::Text::Error))
+                    }
+                };
+                let forward = Some(Message::Forward{id,name:name.clone()}).filter(|_| missing.is_empty());
+                let balance = pick_list(Balance::ALL,Some(data_config.current_balance),move |balance| Message::InputBalance{id,balance});
+                // pods are always watched when connections spread over several
+                let watch = match Kind::parse(name.as_str()).0.spreads(Some(data_config.current_pin.as_str())) {
+                    true => checkbox("watch pods (always, connections spread over the pods)",true),
+                    false => checkbox("watch pods",data_config.current_watch)
+                        .on_toggle(move |watch| Message::InputWatch{id,watch}),
+                }
+                .style(theme::CheckBox::Inverted);
+
+                let buttons = if running {
+                    row![
+                        button("replace").on_press_maybe(forward),
+                        button("restart").on_press(Message::RestartForward{id,name:name.clone()}),
+                        button("stop").on_press(Message::StopForward{id,name:name.clone()}),
+                    ]
+                } else {
+                    row![button("forward").on_press_maybe(forward)]
+                };
+                let buttons = buttons.push_maybe(profile.map(|member| {
+                    button(if member { "remove from profile" } else { "add to profile" })
+                        .on_press(Message::ToggleProfileTarget{id,name:name.clone()})
+                }));
+
                 column![
-                    forward_input,
-                    button,
-                ].spacing(10)
+                    row![bind_input,forward_input,remote_input,button("add").on_press(Message::AddMapping(id))].spacing(8),
+                    bind_hint,
+                    ports,
+                    mappings,
+                    row![pool_input,balance].spacing(8).push_maybe(pin),
+                    watch,
+                    buttons.spacing(8),
+                ].push_maybe(denied).spacing(10)
 
             }
         };
@@ -197,7 +431,7 @@ impl ForwardBox {
             )
         )
         .width(Length::Fill)
-        .height(150.0)
+        .height(Length::Fill)
         .into()
 
     }